@@ -0,0 +1,242 @@
+//! Benchmarks of drawing a frame with the OpenGL renderer, against the previous renderer that
+//! issued a draw call per pixel.
+//!
+//! Both need a window and an OpenGL context, so the benchmarks are skipped when no display is
+//! available, e.g. in CI.
+
+#[macro_use]
+extern crate criterion;
+extern crate cgmath;
+extern crate chip_8;
+extern crate gl;
+extern crate glutin;
+
+#[allow(dead_code)]
+#[path = "../src/graphics.rs"]
+mod graphics;
+#[allow(dead_code)]
+#[path = "../src/renderer.rs"]
+mod renderer;
+
+use cgmath::prelude::*;
+use cgmath::{Matrix4, Vector3};
+use chip_8::{Processor, HEIGHT, WIDTH};
+use criterion::Criterion;
+use gl::types::*;
+use glutin::{GlContext, GlWindow};
+use graphics::Graphics;
+use renderer::{Colour, Renderer, PLANE_COLOURS};
+use std::ffi::CString;
+use std::mem;
+use std::os::raw::c_void;
+use std::ptr;
+
+const VERTEX_SHADER: &str = r#"
+#version 330 core
+layout (location = 0) in vec3 position;
+uniform mat4 translate;
+void main() {
+    gl_Position = translate * vec4(position, 1.0);
+}
+"#;
+
+const FRAGMENT_SHADER: &str = r#"
+#version 330 core
+out vec4 fragment_colour;
+uniform vec3 colour;
+void main() {
+    fragment_colour = vec4(colour, 1.0);
+}
+"#;
+
+// A square of one by one, scaled to the size of a pixel when drawn.
+const VERTICES: [GLfloat; 12] = [
+    // top left
+    0.0, 0.0, 0.0, //
+    // top right
+    1.0, 0.0, 0.0, //
+    // bottom right
+    1.0, -1.0, 0.0, //
+    // bottom left
+    0.0, -1.0, 0.0, //
+];
+const INDICES: [GLint; 6] = [
+    0, 1, 2, // first triangle
+    2, 3, 0, // second triangle
+];
+
+/// The renderer as it was before pixels were batched: every pixel sets the colour and the
+/// translation of a unit square, looking up their uniforms, and draws it.
+struct PerPixel<'a> {
+    gl_window: &'a GlWindow,
+    shader_program: GLuint,
+    // The width and height of a pixel in normalised device coordinates.
+    x_unit: GLfloat,
+    y_unit: GLfloat,
+}
+
+impl<'a> PerPixel<'a> {
+    /// Create the renderer for `gl_window`, whose context must be current, and make its shader
+    /// program and vertex array current.
+    fn new(gl_window: &'a GlWindow) -> Result<PerPixel<'a>, String> {
+        let shader_program = graphics::compile_program(VERTEX_SHADER, FRAGMENT_SHADER)?;
+        unsafe {
+            gl::UseProgram(shader_program);
+
+            let (mut vao, mut vbo, mut ebo) = (0, 0, 0);
+            gl::GenVertexArrays(1, &mut vao);
+            gl::GenBuffers(1, &mut vbo);
+            gl::GenBuffers(1, &mut ebo);
+            gl::BindVertexArray(vao);
+
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                (VERTICES.len() * mem::size_of::<GLfloat>()) as GLsizeiptr,
+                &VERTICES[0] as *const f32 as *const c_void,
+                gl::STATIC_DRAW,
+            );
+
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ebo);
+            gl::BufferData(
+                gl::ELEMENT_ARRAY_BUFFER,
+                (INDICES.len() * mem::size_of::<GLint>()) as GLsizeiptr,
+                &INDICES[0] as *const i32 as *const c_void,
+                gl::STATIC_DRAW,
+            );
+
+            gl::VertexAttribPointer(
+                0,
+                3,
+                gl::FLOAT,
+                gl::FALSE,
+                3 * mem::size_of::<GLfloat>() as GLsizei,
+                ptr::null(),
+            );
+            gl::EnableVertexAttribArray(0);
+
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        Ok(PerPixel {
+            gl_window,
+            shader_program,
+            x_unit: 2.0 / WIDTH as GLfloat,
+            y_unit: 2.0 / HEIGHT as GLfloat,
+        })
+    }
+
+    /// Return the location of the uniform `name` of the shader program.
+    fn uniform(&self, name: &str) -> GLint {
+        // Unwrap is safe, because CString::new() only returns Err when a nul-byte is found.
+        let name = CString::new(name).unwrap();
+        unsafe { gl::GetUniformLocation(self.shader_program, name.as_ptr()) }
+    }
+}
+
+impl<'a> Renderer for PerPixel<'a> {
+    fn set_resolution(&mut self, width: usize, height: usize) {
+        self.x_unit = 2.0 / width as GLfloat;
+        self.y_unit = 2.0 / height as GLfloat;
+    }
+
+    fn clear(&mut self, (red, green, blue): Colour) {
+        unsafe {
+            gl::ClearColor(red, green, blue, 1.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+        }
+    }
+
+    fn draw_pixel(&mut self, x: usize, y: usize, (red, green, blue): Colour) {
+        let translate = Matrix4::<f32>::from_translation(Vector3::<f32>::new(
+            -1.0 + x as f32 * self.x_unit,
+            1.0 - y as f32 * self.y_unit,
+            0.0,
+        )) * Matrix4::<f32>::from_nonuniform_scale(self.x_unit, self.y_unit, 1.0);
+        unsafe {
+            gl::Uniform3f(self.uniform("colour"), red, green, blue);
+            gl::UniformMatrix4fv(self.uniform("translate"), 1, gl::FALSE, translate.as_ptr());
+            gl::DrawElements(gl::TRIANGLES, 6, gl::UNSIGNED_INT, ptr::null());
+        }
+    }
+
+    fn present(&mut self) {
+        self.gl_window.swap_buffers().unwrap();
+    }
+}
+
+impl<'a> Drop for PerPixel<'a> {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteProgram(self.shader_program);
+        }
+    }
+}
+
+/// Return a processor whose display has about two in five pixels set, in high resolution if
+/// `hires`.
+fn processor(hires: bool) -> Processor {
+    let mut processor = Processor::with_seed(0);
+    if hires {
+        // HIGH
+        processor.load_file(&[0x00, 0xFF]).unwrap();
+        processor.step().unwrap();
+    }
+    for y in 0..processor.height() {
+        for x in 0..processor.width() {
+            processor.set_pixel(x, y, (x * 7 + y * 3) % 5 < 2);
+        }
+    }
+    processor
+}
+
+/// Open a hidden window with an OpenGL context, or return `None` if there is no display.
+fn window() -> Option<GlWindow> {
+    if cfg!(all(unix, not(target_os = "macos")))
+        && std::env::var_os("DISPLAY").is_none()
+        && std::env::var_os("WAYLAND_DISPLAY").is_none()
+    {
+        return None;
+    }
+    let events_loop = glutin::EventsLoop::new();
+    let window = glutin::WindowBuilder::new()
+        .with_title("CHIP-8 benchmark")
+        .with_visibility(false);
+    // Without vsync, presenting a frame does not wait for the display.
+    let context = glutin::ContextBuilder::new().with_vsync(false);
+    let gl_window = GlWindow::new(window, context, &events_loop).ok()?;
+    unsafe {
+        gl_window.make_current().ok()?;
+    }
+    Some(gl_window)
+}
+
+fn draw(c: &mut Criterion) {
+    let gl_window = match window() {
+        Some(gl_window) => gl_window,
+        None => {
+            eprintln!("Skipping the graphics benchmarks: no display is available.");
+            return;
+        }
+    };
+    let frames = [("low_resolution", processor(false)), ("high_resolution", processor(true))];
+    let mut group = c.benchmark_group("draw");
+
+    // `Graphics` makes its shader program and vertex array current when it is created, and
+    // `PerPixel` replaces them with its own, so the batched renderer is measured first.
+    let mut batched = Graphics::new(gl_window).unwrap();
+    for &(name, ref processor) in &frames {
+        group.bench_function(format!("batched/{}", name), |b| {
+            b.iter(|| renderer::draw(&mut batched, processor, &PLANE_COLOURS, None))
+        });
+    }
+    let mut per_pixel = PerPixel::new(batched.window()).unwrap();
+    for &(name, ref processor) in &frames {
+        group.bench_function(format!("per_pixel/{}", name), |b| {
+            b.iter(|| renderer::draw(&mut per_pixel, processor, &PLANE_COLOURS, None))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, draw);
+criterion_main!(benches);
@@ -0,0 +1,65 @@
+//! Benchmarks of the throughput of the processor, run without a host with `Processor::run_for`.
+//!
+//! The throughput is reported in instructions per second.
+
+#[macro_use]
+extern crate criterion;
+extern crate chip_8;
+
+use chip_8::Processor;
+use criterion::{BatchSize, Criterion, Throughput};
+
+/// The number of instructions executed per iteration.
+const CYCLES: usize = 100_000;
+
+/// A loop of random numbers, additions and sprites.
+const MIXED: &str = "
+loop:   RND V0, 0x3F
+        RND V1, 0x1F
+        ADD V2, V0
+        ADD V3, V1
+        LD F, V2
+        DRW V0, V1, 5
+        JP loop
+";
+
+/// A loop drawing sprites of 15 rows at random positions, so that most instructions draw.
+const DRAW: &str = "
+        LD I, 0x000
+loop:   RND V0, 0x3F
+        RND V1, 0x1F
+        DRW V0, V1, 15
+        DRW V1, V0, 15
+        DRW V0, V0, 15
+        JP loop
+";
+
+/// Benchmark running the program `source` under `name`.
+fn bench_program(c: &mut Criterion, name: &str, source: &str) {
+    let program = chip_8::assemble(source).unwrap();
+    let mut group = c.benchmark_group("run_for");
+    group.throughput(Throughput::Elements(CYCLES as u64));
+    group.bench_function(name, |b| {
+        b.iter_batched(
+            || {
+                let mut processor = Processor::with_seed(0);
+                processor.load_file(&program).unwrap();
+                processor
+            },
+            |mut processor| processor.run_for(CYCLES).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn mixed(c: &mut Criterion) {
+    bench_program(c, "mixed", MIXED);
+}
+
+fn draw(c: &mut Criterion) {
+    bench_program(c, "draw", DRAW);
+}
+
+criterion_group!(benches, mixed, draw);
+criterion_main!(benches);
@@ -0,0 +1,373 @@
+//! Assembling programs from the assembly syntax of Cowgod's technical reference.
+
+#[cfg(not(feature = "std"))]
+use alloc::collections::BTreeMap;
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use instruction::Instruction;
+use instruction::Instruction::*;
+#[cfg(feature = "std")]
+use std::collections::BTreeMap;
+
+/// An operand of an instruction.
+enum Operand<'a> {
+    /// A register Vx.
+    Register(u8),
+    /// A number.
+    Number(u32),
+    /// The address of a label.
+    Label(&'a str),
+    /// The index register, `I`.
+    I,
+    /// The memory at the index register, `[I]`.
+    IndirectI,
+    /// The delay timer, `DT`.
+    Dt,
+    /// The sound timer, `ST`.
+    St,
+    /// A key press, `K`.
+    K,
+    /// The font, `F`.
+    F,
+    /// The high-resolution font, `HF`.
+    Hf,
+    /// The BCD representation, `B`.
+    B,
+    /// The RPL user flags, `R`.
+    R,
+    /// The 16-bit address in the next word, `LONG`.
+    Long,
+}
+
+/// Assemble `source` into the bytes of a program, to be loaded at 0x200.
+///
+/// The syntax is that of `disassemble`, one instruction per line, e.g. `LD V3, 0x2A`,
+/// `DRW V0, V1, 5` or `JP 0x2F8`. Mnemonics are case-insensitive. Numbers are decimal, or
+/// hexadecimal with `0x`. Everything after a `;` is a comment.
+///
+/// A line can start with a label, e.g. `loop:`, which can be used in place of an address.
+/// `DB` and `DW` insert bytes and words of data, e.g. `DB 0xF0, 0x90` for sprites or `DW 0x1234`
+/// for the address following `LD I, LONG`.
+///
+/// If a line is invalid, the error gives its number.
+pub fn assemble(source: &str) -> Result<Vec<u8>, String> {
+    let mut assembler = Assembler {
+        bytes: Vec::new(),
+        labels: BTreeMap::new(),
+        fixups: Vec::new(),
+    };
+    for (number, line) in source.lines().enumerate() {
+        assembler
+            .assemble_line(line, number + 1)
+            .map_err(|e| format!("line {}: {}", number + 1, e))?;
+    }
+    assembler.resolve_labels()?;
+    Ok(assembler.bytes)
+}
+
+/// The state of the assembler.
+struct Assembler<'a> {
+    /// The bytes assembled so far.
+    bytes: Vec<u8>,
+    /// The offsets of the labels into `bytes`.
+    labels: BTreeMap<&'a str, usize>,
+    /// The references to labels to fill in once all labels are known, as the offset of the
+    /// instruction, the line number and the label.
+    fixups: Vec<(usize, usize, &'a str)>,
+}
+
+impl<'a> Assembler<'a> {
+    /// Assemble the line `line`, number `number`.
+    fn assemble_line(&mut self, line: &'a str, number: usize) -> Result<(), String> {
+        let mut line = line.split(';').next().unwrap_or("").trim();
+        if let Some(colon) = line.find(':') {
+            let label = line[..colon].trim();
+            if !is_identifier(label) {
+                return Err(format!("invalid label {}", label));
+            }
+            if self.labels.insert(label, self.bytes.len()).is_some() {
+                return Err(format!("label {} is already defined", label));
+            }
+            line = line[colon + 1..].trim();
+        }
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        let (mnemonic, operands) = match line.find(char::is_whitespace) {
+            Some(space) => (&line[..space], line[space..].trim()),
+            None => (line, ""),
+        };
+        let mnemonic = mnemonic.to_ascii_uppercase();
+        let operands = if operands.is_empty() {
+            Vec::new()
+        } else {
+            operands
+                .split(',')
+                .map(|operand| parse_operand(operand.trim()))
+                .collect::<Result<Vec<_>, _>>()?
+        };
+
+        match (mnemonic.as_str(), &operands[..]) {
+            ("DB", bytes) if !bytes.is_empty() => {
+                for operand in bytes {
+                    let byte = byte(number_operand(operand)?)?;
+                    self.bytes.push(byte);
+                }
+                Ok(())
+            }
+            ("DW", words) if !words.is_empty() => {
+                for operand in words {
+                    let word = number_operand(operand)?;
+                    if word > 0xFFFF {
+                        return Err(format!("{} does not fit in a word", word));
+                    }
+                    self.bytes.push((word >> 8) as u8);
+                    self.bytes.push(word as u8);
+                }
+                Ok(())
+            }
+            (mnemonic, operands) => {
+                let instruction = self.instruction(mnemonic, operands, number)?;
+                let opcode = instruction.encode();
+                self.bytes.push((opcode >> 8) as u8);
+                self.bytes.push(opcode as u8);
+                Ok(())
+            }
+        }
+    }
+
+    /// Return the instruction `mnemonic` with `operands`, on line `number`.
+    fn instruction(
+        &mut self,
+        mnemonic: &str,
+        operands: &[Operand<'a>],
+        number: usize,
+    ) -> Result<Instruction, String> {
+        use self::Operand::*;
+
+        let instruction = match (mnemonic, operands) {
+            ("CLS", []) => Cls,
+            ("RET", []) => Ret,
+            ("SCR", []) => Scr,
+            ("SCL", []) => Scl,
+            ("EXIT", []) => Exit,
+            ("LOW", []) => Low,
+            ("HIGH", []) => High,
+            ("AUDIO", []) => Audio,
+            ("SCD", [Number(n)]) => Scd(nibble(*n)?),
+            ("SCU", [Number(n)]) => Scu(nibble(*n)?),
+            ("SYS", [address]) => Sys(self.address(address, number)?),
+            ("JP", [Register(0), address]) => JumpV0(self.address(address, number)?),
+            ("JP", [address]) => Jump(self.address(address, number)?),
+            ("CALL", [address]) => Call(self.address(address, number)?),
+            ("SE", [Register(x), Register(y)]) => SeReg { x: *x, y: *y },
+            ("SE", [Register(x), Number(kk)]) => SeByte {
+                x: *x,
+                kk: byte(*kk)?,
+            },
+            ("SNE", [Register(x), Register(y)]) => SneReg { x: *x, y: *y },
+            ("SNE", [Register(x), Number(kk)]) => SneByte {
+                x: *x,
+                kk: byte(*kk)?,
+            },
+            ("LD", [Register(x), Register(y)]) => LdReg { x: *x, y: *y },
+            ("LD", [Register(x), Number(kk)]) => LdByte {
+                x: *x,
+                kk: byte(*kk)?,
+            },
+            ("LD", [I, Long]) => LdILong,
+            ("LD", [I, address]) => LdI(self.address(address, number)?),
+            ("LD", [Register(x), Dt]) => LdVxDt { x: *x },
+            ("LD", [Register(x), K]) => LdVxK { x: *x },
+            ("LD", [Dt, Register(x)]) => LdDtVx { x: *x },
+            ("LD", [St, Register(x)]) => LdStVx { x: *x },
+            ("LD", [F, Register(x)]) => LdF { x: *x },
+            ("LD", [Hf, Register(x)]) => LdHf { x: *x },
+            ("LD", [B, Register(x)]) => LdB { x: *x },
+            ("LD", [IndirectI, Register(x)]) => LdIVx { x: *x },
+            ("LD", [Register(x), IndirectI]) => LdVxI { x: *x },
+            ("LD", [R, Register(x)]) => LdRVx { x: rpl_register(*x)? },
+            ("LD", [Register(x), R]) => LdVxR { x: rpl_register(*x)? },
+            ("ADD", [Register(x), Register(y)]) => AddReg { x: *x, y: *y },
+            ("ADD", [Register(x), Number(kk)]) => AddByte {
+                x: *x,
+                kk: byte(*kk)?,
+            },
+            ("ADD", [I, Register(x)]) => AddI { x: *x },
+            ("OR", [Register(x), Register(y)]) => Or { x: *x, y: *y },
+            ("AND", [Register(x), Register(y)]) => And { x: *x, y: *y },
+            ("XOR", [Register(x), Register(y)]) => Xor { x: *x, y: *y },
+            ("SUB", [Register(x), Register(y)]) => Sub { x: *x, y: *y },
+            ("SUBN", [Register(x), Register(y)]) => Subn { x: *x, y: *y },
+            // Vy is optional for the shifts, as only the `shift_uses_vy` quirk uses it.
+            ("SHR", [Register(x), Register(y)]) => Shr { x: *x, y: *y },
+            ("SHR", [Register(x)]) => Shr { x: *x, y: *x },
+            ("SHL", [Register(x), Register(y)]) => Shl { x: *x, y: *y },
+            ("SHL", [Register(x)]) => Shl { x: *x, y: *x },
+            ("RND", [Register(x), Number(kk)]) => Rnd {
+                x: *x,
+                kk: byte(*kk)?,
+            },
+            ("DRW", [Register(x), Register(y), Number(n)]) => Drw {
+                x: *x,
+                y: *y,
+                n: nibble(*n)?,
+            },
+            ("SKP", [Register(x)]) => Skp { x: *x },
+            ("SKNP", [Register(x)]) => Sknp { x: *x },
+            ("PLANE", [Number(n)]) => Plane(nibble(*n)?),
+            _ => return Err(format!("invalid instruction {}", mnemonic)),
+        };
+        Ok(instruction)
+    }
+
+    /// Return the 12-bit address `operand` of an instruction on line `number`.
+    ///
+    /// A label is filled in by `resolve_labels`, so 0 is returned for it.
+    fn address(&mut self, operand: &Operand<'a>, number: usize) -> Result<u16, String> {
+        match *operand {
+            Operand::Number(address) if address <= 0xFFF => Ok(address as u16),
+            Operand::Number(address) => Err(format!("address 0x{:X} is out of range", address)),
+            Operand::Label(label) => {
+                self.fixups.push((self.bytes.len(), number, label));
+                Ok(0)
+            }
+            _ => Err(String::from("expected an address")),
+        }
+    }
+
+    /// Fill in the addresses of the labels used by instructions.
+    fn resolve_labels(&mut self) -> Result<(), String> {
+        for &(offset, number, label) in &self.fixups {
+            let address = match self.labels.get(label) {
+                Some(&label_offset) => 0x200 + label_offset,
+                None => return Err(format!("line {}: unknown label {}", number, label)),
+            };
+            if address > 0xFFF {
+                return Err(format!("line {}: label {} is out of range", number, label));
+            }
+            self.bytes[offset] |= (address >> 8) as u8;
+            self.bytes[offset + 1] = address as u8;
+        }
+        Ok(())
+    }
+}
+
+/// Parse an operand, e.g. `V3`, `0x2A`, `[I]` or a label.
+fn parse_operand(text: &str) -> Result<Operand<'_>, String> {
+    let upper = text.to_ascii_uppercase();
+    let operand = match upper.as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::Dt,
+        "ST" => Operand::St,
+        "K" => Operand::K,
+        "F" => Operand::F,
+        "HF" => Operand::Hf,
+        "B" => Operand::B,
+        "R" => Operand::R,
+        "LONG" => Operand::Long,
+        _ if upper.len() == 2 && upper.starts_with('V') => {
+            match u8::from_str_radix(&upper[1..], 16) {
+                Ok(x) => Operand::Register(x),
+                Err(_) => return Err(format!("invalid register {}", text)),
+            }
+        }
+        _ if text.starts_with(|c: char| c.is_ascii_digit()) => {
+            let number = if upper.starts_with("0X") {
+                u32::from_str_radix(&text[2..], 16)
+            } else {
+                text.parse()
+            };
+            match number {
+                Ok(number) => Operand::Number(number),
+                Err(_) => return Err(format!("invalid number {}", text)),
+            }
+        }
+        _ if is_identifier(text) => Operand::Label(text),
+        _ => return Err(format!("invalid operand {}", text)),
+    };
+    Ok(operand)
+}
+
+/// Return whether `text` can be used as a label.
+fn is_identifier(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Return the number `operand` of a data directive.
+fn number_operand(operand: &Operand) -> Result<u32, String> {
+    match *operand {
+        Operand::Number(number) => Ok(number),
+        _ => Err(String::from("expected a number")),
+    }
+}
+
+/// Check that `number` fits in a byte.
+fn byte(number: u32) -> Result<u8, String> {
+    if number <= 0xFF {
+        Ok(number as u8)
+    } else {
+        Err(format!("{} does not fit in a byte", number))
+    }
+}
+
+/// Check that `number` fits in a nibble.
+fn nibble(number: u32) -> Result<u8, String> {
+    if number <= 0xF {
+        Ok(number as u8)
+    } else {
+        Err(format!("{} does not fit in a nibble", number))
+    }
+}
+
+/// Check that register `x` has an RPL user flag, i.e. `x` is at most 7.
+fn rpl_register(x: u8) -> Result<u8, String> {
+    if x < 8 {
+        Ok(x)
+    } else {
+        Err(format!("there is no RPL user flag for V{:X}", x))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assemble_program() {
+        let source = "
+            start:  CLS
+                    LD V3, 0x2A     ; comment
+                    ld i, sprite
+            loop:   DRW V0, V1, 5
+                    ADD V0, 8
+                    SE V0, 64
+                    JP loop
+                    JP start
+            sprite: DB 0xF0, 0x90
+                    DW 0x1234
+        ";
+        assert_eq!(
+            assemble(source),
+            Ok(vec![
+                0x00, 0xE0, 0x63, 0x2A, 0xA2, 0x10, 0xD0, 0x15, 0x70, 0x08, 0x30, 0x40, 0x12, 0x06,
+                0x12, 0x00, 0xF0, 0x90, 0x12, 0x34,
+            ])
+        );
+    }
+
+    #[test]
+    fn assemble_reports_invalid_line() {
+        assert_eq!(
+            assemble("CLS\nLD V3, 0x100"),
+            Err(String::from("line 2: 256 does not fit in a byte"))
+        );
+        assert_eq!(assemble("JP nowhere"), Err(String::from("line 1: unknown label nowhere")));
+        assert_eq!(assemble("MOV V0, V1"), Err(String::from("line 1: invalid instruction MOV")));
+    }
+}
@@ -0,0 +1,143 @@
+//! Disassembling opcodes into the assembly syntax of Cowgod's technical reference.
+
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use instruction::decode;
+
+/// Return the mnemonic of `opcode`, e.g. `"LD V3, 0x2A"`, `"DRW V0, V1, 5"` or `"JP 0x2F8"`.
+///
+/// The SUPER-CHIP and XO-CHIP instructions are included. Opcodes that are not an instruction are
+/// returned as data, e.g. `"DW 0x5121"`.
+///
+/// `F000` is followed by a 16-bit address in the next word, which is not part of `opcode`, so it
+/// is returned as `"LD I, LONG"`.
+pub fn disassemble(opcode: u16) -> String {
+    use instruction::Instruction::*;
+
+    let instruction = match decode(opcode) {
+        Some(instruction) => instruction,
+        None => return format!("DW 0x{:04X}", opcode),
+    };
+    match instruction {
+        Sys(nnn) => format!("SYS 0x{:03X}", nnn),
+        Scd(n) => format!("SCD {}", n),
+        Scu(n) => format!("SCU {}", n),
+        Cls => String::from("CLS"),
+        Ret => String::from("RET"),
+        Scr => String::from("SCR"),
+        Scl => String::from("SCL"),
+        Exit => String::from("EXIT"),
+        Low => String::from("LOW"),
+        High => String::from("HIGH"),
+        Jump(nnn) => format!("JP 0x{:03X}", nnn),
+        Call(nnn) => format!("CALL 0x{:03X}", nnn),
+        SeByte { x, kk } => format!("SE V{:X}, 0x{:02X}", x, kk),
+        SneByte { x, kk } => format!("SNE V{:X}, 0x{:02X}", x, kk),
+        SeReg { x, y } => format!("SE V{:X}, V{:X}", x, y),
+        LdByte { x, kk } => format!("LD V{:X}, 0x{:02X}", x, kk),
+        AddByte { x, kk } => format!("ADD V{:X}, 0x{:02X}", x, kk),
+        LdReg { x, y } => format!("LD V{:X}, V{:X}", x, y),
+        Or { x, y } => format!("OR V{:X}, V{:X}", x, y),
+        And { x, y } => format!("AND V{:X}, V{:X}", x, y),
+        Xor { x, y } => format!("XOR V{:X}, V{:X}", x, y),
+        AddReg { x, y } => format!("ADD V{:X}, V{:X}", x, y),
+        Sub { x, y } => format!("SUB V{:X}, V{:X}", x, y),
+        Shr { x, y } => format!("SHR V{:X}, V{:X}", x, y),
+        Subn { x, y } => format!("SUBN V{:X}, V{:X}", x, y),
+        Shl { x, y } => format!("SHL V{:X}, V{:X}", x, y),
+        SneReg { x, y } => format!("SNE V{:X}, V{:X}", x, y),
+        LdI(nnn) => format!("LD I, 0x{:03X}", nnn),
+        JumpV0(nnn) => format!("JP V0, 0x{:03X}", nnn),
+        Rnd { x, kk } => format!("RND V{:X}, 0x{:02X}", x, kk),
+        Drw { x, y, n } => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        Skp { x } => format!("SKP V{:X}", x),
+        Sknp { x } => format!("SKNP V{:X}", x),
+        LdILong => String::from("LD I, LONG"),
+        Plane(n) => format!("PLANE {}", n),
+        Audio => String::from("AUDIO"),
+        LdVxDt { x } => format!("LD V{:X}, DT", x),
+        LdVxK { x } => format!("LD V{:X}, K", x),
+        LdDtVx { x } => format!("LD DT, V{:X}", x),
+        LdStVx { x } => format!("LD ST, V{:X}", x),
+        AddI { x } => format!("ADD I, V{:X}", x),
+        LdF { x } => format!("LD F, V{:X}", x),
+        LdHf { x } => format!("LD HF, V{:X}", x),
+        LdB { x } => format!("LD B, V{:X}", x),
+        LdIVx { x } => format!("LD [I], V{:X}", x),
+        LdVxI { x } => format!("LD V{:X}, [I]", x),
+        LdRVx { x } => format!("LD R, V{:X}", x),
+        LdVxR { x } => format!("LD V{:X}, R", x),
+    }
+}
+
+/// Return the words that differ between the ROMs `old` and `new`, as the address with the ROMs
+/// loaded at 0x200, the word in `old` and the word in `new`.
+///
+/// The ROMs are aligned by address, so a word is `None` if the address is past the end of its ROM,
+/// i.e. the word was inserted into or removed from the end of `new`. A final odd byte is compared
+/// as a word whose low byte is 0.
+pub fn diff_roms(old: &[u8], new: &[u8]) -> Vec<(usize, Option<u16>, Option<u16>)> {
+    let word = |rom: &[u8], offset: usize| {
+        rom.get(offset)
+            .map(|&high| (high as u16) << 8 | rom.get(offset + 1).map_or(0, |&low| low as u16))
+    };
+    (0..old.len().max(new.len()))
+        .step_by(2)
+        .map(|offset| (0x200 + offset, word(old, offset), word(new, offset)))
+        .filter(|&(_, old, new)| old != new)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_roms_finds_changed_instruction() {
+        // LD V0, 0x05; ADD V0, 0x01; JP 0x200, with the addition changed to ADD V0, 0x02.
+        let old = [0x60, 0x05, 0x70, 0x01, 0x12, 0x00];
+        let new = [0x60, 0x05, 0x70, 0x02, 0x12, 0x00];
+        assert_eq!(diff_roms(&old, &new), vec![(0x202, Some(0x7001), Some(0x7002))]);
+        assert_eq!(diff_roms(&old, &old), vec![]);
+    }
+
+    #[test]
+    fn diff_roms_finds_inserted_instruction() {
+        let old = [0x60, 0x05];
+        let new = [0x60, 0x05, 0x00, 0xE0, 0x12];
+        assert_eq!(
+            diff_roms(&old, &new),
+            vec![(0x202, None, Some(0x00E0)), (0x204, None, Some(0x1200))]
+        );
+        assert_eq!(diff_roms(&new, &old)[0], (0x202, Some(0x00E0), None));
+    }
+
+    #[test]
+    fn disassemble_each_nibble_group() {
+        let cases = [
+            (0x00E0, "CLS"),
+            (0x1234, "JP 0x234"),
+            (0x2ABC, "CALL 0xABC"),
+            (0x3A2B, "SE VA, 0x2B"),
+            (0x4105, "SNE V1, 0x05"),
+            (0x5120, "SE V1, V2"),
+            (0x6F00, "LD VF, 0x00"),
+            (0x7301, "ADD V3, 0x01"),
+            (0x8AB4, "ADD VA, VB"),
+            (0x9CD0, "SNE VC, VD"),
+            (0xA2F8, "LD I, 0x2F8"),
+            (0xB300, "JP V0, 0x300"),
+            (0xC10F, "RND V1, 0x0F"),
+            (0xD015, "DRW V0, V1, 5"),
+            (0xE59E, "SKP V5"),
+            (0xF233, "LD B, V2"),
+        ];
+        for &(opcode, mnemonic) in &cases {
+            assert_eq!(disassemble(opcode), mnemonic);
+        }
+        assert_eq!(disassemble(0x5121), "DW 0x5121");
+        assert_eq!(disassemble(0x00FF), "HIGH");
+    }
+}
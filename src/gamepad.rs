@@ -0,0 +1,113 @@
+//! Mapping gamepad buttons to keys on the CHIP-8 keypad.
+
+extern crate gilrs;
+
+use self::gilrs::{Button, EventType};
+use chip_8::Processor;
+use keymap::{self, BUTTON_PREFIX};
+use std::collections::HashMap;
+
+pub use self::gilrs::Gilrs;
+
+/// A mapping of gamepad buttons to keypad indices.
+pub type ButtonMap = HashMap<Button, usize>;
+
+// Generate `button_from_name`, which returns the button named like its `Button` variant.
+macro_rules! button_names {
+    ( $( $name:ident ),* $(,)* ) => {
+        /// Return the button called `name`, which is the name of its `Button` variant (e.g.
+        /// `"South"` or `"DPadUp"`).
+        fn button_from_name(name: &str) -> Option<Button> {
+            match name {
+                $( stringify!($name) => Some(Button::$name), )*
+                _ => None,
+            }
+        }
+    };
+}
+
+button_names! {
+    South, East, North, West, C, Z,
+    LeftTrigger, LeftTrigger2, RightTrigger, RightTrigger2,
+    Select, Start, Mode, LeftThumb, RightThumb,
+    DPadUp, DPadDown, DPadLeft, DPadRight,
+}
+
+/// Return the default mapping of the D-pad onto 2, 4, 6 and 8, which most games use for
+/// directions, and of the face buttons onto 5, A, E and F.
+pub fn default_button_map() -> ButtonMap {
+    use self::gilrs::Button::*;
+
+    [
+        (DPadUp, 0x2),
+        (DPadLeft, 0x4),
+        (DPadRight, 0x6),
+        (DPadDown, 0x8),
+        (South, 0x5),
+        (East, 0xA),
+        (North, 0xE),
+        (West, 0xF),
+    ]
+    .iter()
+    .cloned()
+    .collect()
+}
+
+/// Parse the gamepad buttons in a keymap file.
+///
+/// Buttons are named `BUTTON_PREFIX` followed by their `Button` variant, e.g.
+/// `GamepadDPadUp = 2`. The lines mapping keyboard keys are skipped; see `keymap::parse_keymap`.
+pub fn parse_button_map(text: &str) -> Result<ButtonMap, String> {
+    let mut button_map = ButtonMap::new();
+    for (number, name, index) in keymap::parse_entries(text)? {
+        if !name.starts_with(BUTTON_PREFIX) {
+            continue;
+        }
+        let button = button_from_name(&name[BUTTON_PREFIX.len()..])
+            .ok_or_else(|| format!("line {}: unknown button {}", number, name))?;
+        button_map.insert(button, index);
+    }
+    Ok(button_map)
+}
+
+/// Press and release the keys of `processor` mapped in `button_map` for the button events
+/// received since the last call.
+pub fn poll(gilrs: &mut Gilrs, button_map: &ButtonMap, processor: &mut Processor) {
+    while let Some(event) = gilrs.next_event() {
+        let (button, pressed) = match event.event {
+            EventType::ButtonPressed(button, _) => (button, true),
+            EventType::ButtonReleased(button, _) => (button, false),
+            _ => continue,
+        };
+        if let Some(&key) = button_map.get(&button) {
+            processor.set_key(key, pressed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_button_map_reads_buttons() {
+        let text = "Q = 4\nGamepadDPadUp = 2\nGamepadSouth = 0xB\n";
+        let button_map = parse_button_map(text).unwrap();
+        assert_eq!(button_map.len(), 2);
+        assert_eq!(button_map[&Button::DPadUp], 0x2);
+        assert_eq!(button_map[&Button::South], 0xB);
+        assert_eq!(
+            parse_button_map("GamepadTurbo = 1"),
+            Err(String::from("line 1: unknown button GamepadTurbo"))
+        );
+    }
+
+    #[test]
+    fn default_button_map_maps_directions() {
+        let button_map = default_button_map();
+        assert_eq!(button_map[&Button::DPadUp], 0x2);
+        assert_eq!(button_map[&Button::DPadLeft], 0x4);
+        assert_eq!(button_map[&Button::DPadRight], 0x6);
+        assert_eq!(button_map[&Button::DPadDown], 0x8);
+    }
+}
@@ -6,200 +6,233 @@ use self::cgmath::prelude::*;
 use self::cgmath::{Matrix4, Vector3};
 use self::gl::types::*;
 use self::glutin::{GlContext, GlWindow};
-use super::{HEIGHT, WIDTH};
+use chip_8::{HEIGHT, WIDTH};
+use renderer::{Colour, Renderer};
 use std::ffi::{CStr, CString};
 use std::mem;
 use std::os::raw::c_void;
 use std::ptr;
 
+// The position is in pixels, which `translate` maps to normalised device coordinates.
 const VERTEX_SHADER: &str = r#"
 #version 330 core
-layout (location = 0) in vec3 position;
+layout (location = 0) in vec2 position;
+layout (location = 1) in vec3 colour;
 uniform mat4 translate;
+out vec3 vertex_colour;
 void main() {
-    gl_Position = translate * vec4(position, 1.0);
+    gl_Position = translate * vec4(position, 0.0, 1.0);
+    vertex_colour = colour;
 }
 "#;
 
 const FRAGMENT_SHADER: &str = r#"
 #version 330 core
+in vec3 vertex_colour;
 out vec4 fragment_colour;
 void main() {
-    fragment_colour = vec4(1.0, 1.0, 1.0, 1.0);
+    fragment_colour = vec4(vertex_colour, 1.0);
 }
 "#;
 
-const X_UNIT: GLfloat = 2.0 / WIDTH as GLfloat;
-const Y_UNIT: GLfloat = 2.0 / HEIGHT as GLfloat;
-
-const VERTICES: [GLfloat; 12] = [
-    // top left
-    -1.0,
-    1.0,
-    0.0,
-    // top right
-    -1.0 + X_UNIT,
-    1.0,
-    0.0,
-    // bottom right
-    -1.0 + X_UNIT,
-    1.0 - Y_UNIT,
-    0.0,
-    // bottom left
-    -1.0,
-    1.0 - Y_UNIT,
-    0.0,
-];
-const INDICES: [GLint; 6] = [
-    0, 1, 2, // first triangle
-    2, 3, 0, // second triangle
+// The corners of the two triangles making up a pixel, relative to its top left corner.
+const PIXEL_CORNERS: [(GLfloat, GLfloat); 6] = [
+    // first triangle
+    (0.0, 0.0),
+    (1.0, 0.0),
+    (1.0, 1.0),
+    // second triangle
+    (1.0, 1.0),
+    (0.0, 1.0),
+    (0.0, 0.0),
 ];
+// The number of floats per vertex: the position and the colour.
+const VERTEX_SIZE: usize = 5;
 
-#[derive(Default)]
+/// The OpenGL `Renderer`, drawing into a window.
+///
+/// The pixels of a frame are collected into a single vertex buffer, which is drawn at once when
+/// the frame is presented.
 pub struct Graphics {
+    gl_window: GlWindow,
     shader_program: GLuint,
+    vertex_buffer: GLuint,
+    translate_uniform: GLint,
+    // The size of the display in pixels.
+    width: usize,
+    height: usize,
+    // The vertices of the pixels drawn since the last clear.
+    vertices: Vec<GLfloat>,
 }
 
 impl Graphics {
-    pub fn new() -> Graphics {
-        Graphics::default()
+    /// Create the renderer for `gl_window`, whose context must be current.
+    pub fn new(gl_window: GlWindow) -> Result<Graphics, String> {
+        let mut graphics = Graphics {
+            gl_window,
+            shader_program: 0,
+            vertex_buffer: 0,
+            translate_uniform: 0,
+            width: WIDTH,
+            height: HEIGHT,
+            vertices: Vec::new(),
+        };
+        graphics.init()?;
+        Ok(graphics)
+    }
+
+    pub fn window(&self) -> &GlWindow {
+        &self.gl_window
     }
 
-    pub fn init(&mut self, gl_window: &GlWindow) -> Result<(), String> {
+    fn init(&mut self) -> Result<(), String> {
+        let gl_window = &self.gl_window;
         gl::load_with(|symbol| gl_window.get_proc_address(symbol) as *const _);
 
+        self.shader_program = compile_program(VERTEX_SHADER, FRAGMENT_SHADER)?;
         unsafe {
-            let mut success = GLint::from(gl::FALSE);
-            let mut info_log = Vec::with_capacity(512);
-            info_log.set_len(512 - 1);
-
-            // Compile vertex shader.
-            let vertex_shader = gl::CreateShader(gl::VERTEX_SHADER);
-            let c_str_vert = CString::new(VERTEX_SHADER).unwrap();
-            gl::ShaderSource(vertex_shader, 1, &c_str_vert.as_ptr(), ptr::null());
-            gl::CompileShader(vertex_shader);
-
-            // Check for vertex shader compilation errors.
-            gl::GetShaderiv(vertex_shader, gl::COMPILE_STATUS, &mut success);
-            if success != GLint::from(gl::TRUE) {
-                gl::GetShaderInfoLog(
-                    vertex_shader,
-                    512,
-                    ptr::null_mut(),
-                    info_log.as_mut_ptr() as *mut GLchar,
-                );
-                return Err(format!(
-                    "vertex shader compilation failed: {}",
-                    CStr::from_ptr(info_log.as_ptr()).to_string_lossy(),
-                ));
-            }
-
-            // Compile fragment shader.
-            let fragment_shader = gl::CreateShader(gl::FRAGMENT_SHADER);
-            let c_str_frag = CString::new(FRAGMENT_SHADER).unwrap();
-            gl::ShaderSource(fragment_shader, 1, &c_str_frag.as_ptr(), ptr::null());
-            gl::CompileShader(fragment_shader);
-
-            // Check for fragment shader compilation errors.
-            gl::GetShaderiv(fragment_shader, gl::COMPILE_STATUS, &mut success);
-            if success != GLint::from(gl::TRUE) {
-                gl::GetShaderInfoLog(
-                    fragment_shader,
-                    512,
-                    ptr::null_mut(),
-                    info_log.as_mut_ptr() as *mut GLchar,
-                );
-                return Err(format!(
-                    "fragment shader compilation failed: {}",
-                    CStr::from_ptr(info_log.as_ptr()).to_string_lossy(),
-                ));
-            }
-
-            // Link shader program.
-            self.shader_program = gl::CreateProgram();
-            gl::AttachShader(self.shader_program, vertex_shader);
-            gl::AttachShader(self.shader_program, fragment_shader);
-            gl::LinkProgram(self.shader_program);
-
-            // Check for shader program linking errors.
-            gl::GetProgramiv(self.shader_program, gl::LINK_STATUS, &mut success);
-            if success != GLint::from(gl::TRUE) {
-                gl::GetProgramInfoLog(
-                    self.shader_program,
-                    512,
-                    ptr::null_mut(),
-                    info_log.as_mut_ptr() as *mut GLchar,
-                );
-                return Err(format!(
-                    "shader program compilation failed:\n{}",
-                    CStr::from_ptr(info_log.as_ptr()).to_string_lossy(),
-                ));
-            }
-
             gl::UseProgram(self.shader_program);
 
-            gl::DeleteShader(vertex_shader);
-            gl::DeleteShader(fragment_shader);
-            gl::DeleteProgram(self.shader_program);
+            // Unwrap is safe, because CString::new() only returns Err when a nul-byte is found.
+            let translate_str = CString::new("translate").unwrap();
+            self.translate_uniform =
+                gl::GetUniformLocation(self.shader_program, translate_str.as_ptr());
 
-            let (mut vao, mut vbo, mut ebo) = (0, 0, 0);
+            let mut vao = 0;
             gl::GenVertexArrays(1, &mut vao);
-            gl::GenBuffers(1, &mut vbo);
-            gl::GenBuffers(1, &mut ebo);
+            gl::GenBuffers(1, &mut self.vertex_buffer);
             gl::BindVertexArray(vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vertex_buffer);
 
-            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
-            gl::BufferData(
-                gl::ARRAY_BUFFER,
-                (VERTICES.len() * mem::size_of::<GLfloat>()) as GLsizeiptr,
-                &VERTICES[0] as *const f32 as *const c_void,
-                gl::STATIC_DRAW,
-            );
-
-            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ebo);
-            gl::BufferData(
-                gl::ELEMENT_ARRAY_BUFFER,
-                (INDICES.len() * mem::size_of::<GLint>()) as GLsizeiptr,
-                &INDICES[0] as *const i32 as *const c_void,
-                gl::STATIC_DRAW,
-            );
-
+            let stride = (VERTEX_SIZE * mem::size_of::<GLfloat>()) as GLsizei;
+            gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, stride, ptr::null());
+            gl::EnableVertexAttribArray(0);
             gl::VertexAttribPointer(
-                0,
+                1,
                 3,
                 gl::FLOAT,
                 gl::FALSE,
-                3 * mem::size_of::<GLfloat>() as GLsizei,
-                ptr::null(),
+                stride,
+                (2 * mem::size_of::<GLfloat>()) as *const c_void,
             );
-            gl::EnableVertexAttribArray(0);
-
-            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            gl::EnableVertexAttribArray(1);
         }
 
         Ok(())
     }
+}
+
+/// Compile and link a shader program from the sources of its vertex and fragment shaders. The
+/// context must be current and the GL functions loaded.
+pub fn compile_program(vertex_source: &str, fragment_source: &str) -> Result<GLuint, String> {
+    unsafe {
+        let vertex_shader = compile_shader(gl::VERTEX_SHADER, vertex_source, "vertex")?;
+        let fragment_shader = compile_shader(gl::FRAGMENT_SHADER, fragment_source, "fragment")?;
+
+        // Link shader program.
+        let shader_program = gl::CreateProgram();
+        gl::AttachShader(shader_program, vertex_shader);
+        gl::AttachShader(shader_program, fragment_shader);
+        gl::LinkProgram(shader_program);
+        gl::DeleteShader(vertex_shader);
+        gl::DeleteShader(fragment_shader);
+
+        // Check for shader program linking errors.
+        let mut success = GLint::from(gl::FALSE);
+        let mut info_log: Vec<GLchar> = vec![0; 512];
+        gl::GetProgramiv(shader_program, gl::LINK_STATUS, &mut success);
+        if success != GLint::from(gl::TRUE) {
+            gl::GetProgramInfoLog(
+                shader_program,
+                512,
+                ptr::null_mut(),
+                info_log.as_mut_ptr() as *mut GLchar,
+            );
+            return Err(format!(
+                "shader program compilation failed:\n{}",
+                CStr::from_ptr(info_log.as_ptr()).to_string_lossy(),
+            ));
+        }
+        Ok(shader_program)
+    }
+}
+
+/// Compile a shader of type `kind` from `source`, naming it `name` in errors.
+unsafe fn compile_shader(kind: GLenum, source: &str, name: &str) -> Result<GLuint, String> {
+    let shader = gl::CreateShader(kind);
+    // Unwrap is safe, because the shader sources are constants without nul-bytes.
+    let c_str = CString::new(source).unwrap();
+    gl::ShaderSource(shader, 1, &c_str.as_ptr(), ptr::null());
+    gl::CompileShader(shader);
+
+    // Check for shader compilation errors.
+    let mut success = GLint::from(gl::FALSE);
+    let mut info_log: Vec<GLchar> = vec![0; 512];
+    gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut success);
+    if success != GLint::from(gl::TRUE) {
+        gl::GetShaderInfoLog(shader, 512, ptr::null_mut(), info_log.as_mut_ptr() as *mut GLchar);
+        return Err(format!(
+            "{} shader compilation failed: {}",
+            name,
+            CStr::from_ptr(info_log.as_ptr()).to_string_lossy(),
+        ));
+    }
+    Ok(shader)
+}
+
+impl Renderer for Graphics {
+    fn set_resolution(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+    }
 
-    pub fn clear_colour(&self, red: f32, green: f32, blue: f32, alpha: f32) {
+    fn clear(&mut self, (red, green, blue): Colour) {
+        self.vertices.clear();
         unsafe {
-            gl::ClearColor(red, green, blue, alpha);
+            gl::ClearColor(red, green, blue, 1.0);
             gl::Clear(gl::COLOR_BUFFER_BIT);
         }
     }
 
-    pub fn draw_square_at(&self, x: usize, y: usize) {
-        let translate = Matrix4::<f32>::from_translation(Vector3::<f32>::new(
-            x as f32 * X_UNIT,
-            y as f32 * -Y_UNIT,
-            0.0,
-        ));
+    fn draw_pixel(&mut self, x: usize, y: usize, (red, green, blue): Colour) {
+        for &(dx, dy) in &PIXEL_CORNERS {
+            self.vertices.extend_from_slice(&[
+                x as GLfloat + dx,
+                y as GLfloat + dy,
+                red,
+                green,
+                blue,
+            ]);
+        }
+    }
+
+    fn present(&mut self) {
+        // Map (0, 0) to the top left corner and (width, height) to the bottom right corner.
+        let x_unit = 2.0 / self.width as GLfloat;
+        let y_unit = 2.0 / self.height as GLfloat;
+        let translate = Matrix4::<f32>::from_translation(Vector3::<f32>::new(-1.0, 1.0, 0.0))
+            * Matrix4::<f32>::from_nonuniform_scale(x_unit, -y_unit, 1.0);
         unsafe {
-            // Unwrap is safe, because CString::new() only returns Err when a nul-byte is found.
-            let translate_str = CString::new("translate").unwrap();
-            let translate_uniform =
-                gl::GetUniformLocation(self.shader_program, translate_str.as_ptr());
-            gl::UniformMatrix4fv(translate_uniform, 1, gl::FALSE, translate.as_ptr());
-            gl::DrawElements(gl::TRIANGLES, 6, gl::UNSIGNED_INT, ptr::null());
+            gl::UniformMatrix4fv(self.translate_uniform, 1, gl::FALSE, translate.as_ptr());
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                (self.vertices.len() * mem::size_of::<GLfloat>()) as GLsizeiptr,
+                self.vertices.as_ptr() as *const c_void,
+                gl::STREAM_DRAW,
+            );
+            gl::DrawArrays(gl::TRIANGLES, 0, (self.vertices.len() / VERTEX_SIZE) as GLsizei);
+        }
+        self.gl_window.swap_buffers().unwrap();
+    }
+}
+
+impl Drop for Graphics {
+    fn drop(&mut self) {
+        // The program is in use for as long as the renderer exists. The window, and with it the
+        // context, is only dropped after this.
+        unsafe {
+            gl::DeleteBuffers(1, &self.vertex_buffer);
+            gl::DeleteProgram(self.shader_program);
         }
     }
 }
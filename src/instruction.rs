@@ -0,0 +1,337 @@
+//! Decoding opcodes into instructions.
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+/// A decoded instruction, named after its mnemonic in Cowgod's technical reference.
+///
+/// `x` and `y` are register indices, `kk` is a byte, `n` is a nibble and the addresses are 12-bit,
+/// except for `LdILong`, whose 16-bit address is in the word following the opcode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Instruction {
+    /// 0nnn - SYS addr
+    Sys(u16),
+    /// 00Cn - SCD nibble (SUPER-CHIP)
+    Scd(u8),
+    /// 00Dn - SCU nibble (XO-CHIP)
+    Scu(u8),
+    /// 00E0 - CLS
+    Cls,
+    /// 00EE - RET
+    Ret,
+    /// 00FB - SCR (SUPER-CHIP)
+    Scr,
+    /// 00FC - SCL (SUPER-CHIP)
+    Scl,
+    /// 00FD - EXIT (SUPER-CHIP)
+    Exit,
+    /// 00FE - LOW (SUPER-CHIP)
+    Low,
+    /// 00FF - HIGH (SUPER-CHIP)
+    High,
+    /// 1nnn - JP addr
+    Jump(u16),
+    /// 2nnn - CALL addr
+    Call(u16),
+    /// 3xkk - SE Vx, byte
+    SeByte { x: u8, kk: u8 },
+    /// 4xkk - SNE Vx, byte
+    SneByte { x: u8, kk: u8 },
+    /// 5xy0 - SE Vx, Vy
+    SeReg { x: u8, y: u8 },
+    /// 6xkk - LD Vx, byte
+    LdByte { x: u8, kk: u8 },
+    /// 7xkk - ADD Vx, byte
+    AddByte { x: u8, kk: u8 },
+    /// 8xy0 - LD Vx, Vy
+    LdReg { x: u8, y: u8 },
+    /// 8xy1 - OR Vx, Vy
+    Or { x: u8, y: u8 },
+    /// 8xy2 - AND Vx, Vy
+    And { x: u8, y: u8 },
+    /// 8xy3 - XOR Vx, Vy
+    Xor { x: u8, y: u8 },
+    /// 8xy4 - ADD Vx, Vy
+    AddReg { x: u8, y: u8 },
+    /// 8xy5 - SUB Vx, Vy
+    Sub { x: u8, y: u8 },
+    /// 8xy6 - SHR Vx {, Vy}
+    Shr { x: u8, y: u8 },
+    /// 8xy7 - SUBN Vx, Vy
+    Subn { x: u8, y: u8 },
+    /// 8xyE - SHL Vx {, Vy}
+    Shl { x: u8, y: u8 },
+    /// 9xy0 - SNE Vx, Vy
+    SneReg { x: u8, y: u8 },
+    /// Annn - LD I, addr
+    LdI(u16),
+    /// Bnnn - JP V0, addr
+    JumpV0(u16),
+    /// Cxkk - RND Vx, byte
+    Rnd { x: u8, kk: u8 },
+    /// Dxyn - DRW Vx, Vy, nibble
+    Drw { x: u8, y: u8, n: u8 },
+    /// Ex9E - SKP Vx
+    Skp { x: u8 },
+    /// ExA1 - SKNP Vx
+    Sknp { x: u8 },
+    /// F000 NNNN - LD I, NNNN (XO-CHIP)
+    LdILong,
+    /// Fn01 - PLANE n (XO-CHIP)
+    Plane(u8),
+    /// F002 - AUDIO (XO-CHIP)
+    Audio,
+    /// Fx07 - LD Vx, DT
+    LdVxDt { x: u8 },
+    /// Fx0A - LD Vx, K
+    LdVxK { x: u8 },
+    /// Fx15 - LD DT, Vx
+    LdDtVx { x: u8 },
+    /// Fx18 - LD ST, Vx
+    LdStVx { x: u8 },
+    /// Fx1E - ADD I, Vx
+    AddI { x: u8 },
+    /// Fx29 - LD F, Vx
+    LdF { x: u8 },
+    /// Fx30 - LD HF, Vx (SUPER-CHIP)
+    LdHf { x: u8 },
+    /// Fx33 - LD B, Vx
+    LdB { x: u8 },
+    /// Fx55 - LD [I], Vx
+    LdIVx { x: u8 },
+    /// Fx65 - LD Vx, [I]
+    LdVxI { x: u8 },
+    /// Fx75 - LD R, Vx (SUPER-CHIP)
+    LdRVx { x: u8 },
+    /// Fx85 - LD Vx, R (SUPER-CHIP)
+    LdVxR { x: u8 },
+}
+
+impl Instruction {
+    /// Encode this instruction as its opcode, so that `decode(opcode).unwrap().encode() == opcode`
+    /// for every opcode that `decode` accepts.
+    ///
+    /// The fields are masked to their width in the opcode, e.g. only the low nibble of `x` is
+    /// used. `LdILong` encodes only the `F000` word, not the address following it.
+    pub fn encode(&self) -> u16 {
+        use self::Instruction::*;
+
+        // Build an opcode from its nibbles and low byte.
+        let op = |high: u16, x: u8, kk: u16| high << 12 | (x as u16 & 0xF) << 8 | kk & 0xFF;
+        let xy = |high: u16, x: u8, y: u8, n: u16| op(high, x, (y as u16 & 0xF) << 4 | n);
+
+        match *self {
+            Sys(nnn) => nnn & 0x0FFF,
+            Scd(n) => 0x00C0 | n as u16 & 0xF,
+            Scu(n) => 0x00D0 | n as u16 & 0xF,
+            Cls => 0x00E0,
+            Ret => 0x00EE,
+            Scr => 0x00FB,
+            Scl => 0x00FC,
+            Exit => 0x00FD,
+            Low => 0x00FE,
+            High => 0x00FF,
+            Jump(nnn) => 0x1000 | nnn & 0x0FFF,
+            Call(nnn) => 0x2000 | nnn & 0x0FFF,
+            SeByte { x, kk } => op(0x3, x, kk as u16),
+            SneByte { x, kk } => op(0x4, x, kk as u16),
+            SeReg { x, y } => xy(0x5, x, y, 0x0),
+            LdByte { x, kk } => op(0x6, x, kk as u16),
+            AddByte { x, kk } => op(0x7, x, kk as u16),
+            LdReg { x, y } => xy(0x8, x, y, 0x0),
+            Or { x, y } => xy(0x8, x, y, 0x1),
+            And { x, y } => xy(0x8, x, y, 0x2),
+            Xor { x, y } => xy(0x8, x, y, 0x3),
+            AddReg { x, y } => xy(0x8, x, y, 0x4),
+            Sub { x, y } => xy(0x8, x, y, 0x5),
+            Shr { x, y } => xy(0x8, x, y, 0x6),
+            Subn { x, y } => xy(0x8, x, y, 0x7),
+            Shl { x, y } => xy(0x8, x, y, 0xE),
+            SneReg { x, y } => xy(0x9, x, y, 0x0),
+            LdI(nnn) => 0xA000 | nnn & 0x0FFF,
+            JumpV0(nnn) => 0xB000 | nnn & 0x0FFF,
+            Rnd { x, kk } => op(0xC, x, kk as u16),
+            Drw { x, y, n } => xy(0xD, x, y, n as u16 & 0xF),
+            Skp { x } => op(0xE, x, 0x9E),
+            Sknp { x } => op(0xE, x, 0xA1),
+            LdILong => 0xF000,
+            Plane(n) => op(0xF, n, 0x01),
+            Audio => 0xF002,
+            LdVxDt { x } => op(0xF, x, 0x07),
+            LdVxK { x } => op(0xF, x, 0x0A),
+            LdDtVx { x } => op(0xF, x, 0x15),
+            LdStVx { x } => op(0xF, x, 0x18),
+            AddI { x } => op(0xF, x, 0x1E),
+            LdF { x } => op(0xF, x, 0x29),
+            LdHf { x } => op(0xF, x, 0x30),
+            LdB { x } => op(0xF, x, 0x33),
+            LdIVx { x } => op(0xF, x, 0x55),
+            LdVxI { x } => op(0xF, x, 0x65),
+            LdRVx { x } => op(0xF, x, 0x75),
+            LdVxR { x } => op(0xF, x, 0x85),
+        }
+    }
+}
+
+/// Decode `opcode` into an `Instruction`, or return `None` if it is not an instruction.
+///
+/// The SUPER-CHIP and XO-CHIP instructions are always decoded, whatever the mode of a processor.
+/// Opcodes with nonzero bits where their encoding has zeros, such as `5xy1`, are not instructions,
+/// although a processor ignores these bits unless the `strict_encoding` quirk is set.
+pub fn decode(opcode: u16) -> Option<Instruction> {
+    use self::Instruction::*;
+
+    let x = ((opcode & 0x0F00) >> 8) as u8;
+    let y = ((opcode & 0x00F0) >> 4) as u8;
+    let n = (opcode & 0x000F) as u8;
+    let kk = opcode as u8;
+    let nnn = opcode & 0x0FFF;
+
+    let instruction = match (opcode & 0xF000) >> 12 {
+        0x0 => match nnn {
+            0x0C0..=0x0CF => Scd(n),
+            0x0D0..=0x0DF => Scu(n),
+            0x0E0 => Cls,
+            0x0EE => Ret,
+            0x0FB => Scr,
+            0x0FC => Scl,
+            0x0FD => Exit,
+            0x0FE => Low,
+            0x0FF => High,
+            _ => Sys(nnn),
+        },
+        0x1 => Jump(nnn),
+        0x2 => Call(nnn),
+        0x3 => SeByte { x, kk },
+        0x4 => SneByte { x, kk },
+        0x5 if n == 0 => SeReg { x, y },
+        0x6 => LdByte { x, kk },
+        0x7 => AddByte { x, kk },
+        0x8 => match n {
+            0x0 => LdReg { x, y },
+            0x1 => Or { x, y },
+            0x2 => And { x, y },
+            0x3 => Xor { x, y },
+            0x4 => AddReg { x, y },
+            0x5 => Sub { x, y },
+            0x6 => Shr { x, y },
+            0x7 => Subn { x, y },
+            0xE => Shl { x, y },
+            _ => return None,
+        },
+        0x9 if n == 0 => SneReg { x, y },
+        0xA => LdI(nnn),
+        0xB => JumpV0(nnn),
+        0xC => Rnd { x, kk },
+        0xD => Drw { x, y, n },
+        0xE => match kk {
+            0x9E => Skp { x },
+            0xA1 => Sknp { x },
+            _ => return None,
+        },
+        0xF => match kk {
+            0x00 if x == 0 => LdILong,
+            0x01 => Plane(x),
+            0x02 if x == 0 => Audio,
+            0x07 => LdVxDt { x },
+            0x0A => LdVxK { x },
+            0x15 => LdDtVx { x },
+            0x18 => LdStVx { x },
+            0x1E => AddI { x },
+            0x29 => LdF { x },
+            0x30 => LdHf { x },
+            0x33 => LdB { x },
+            0x55 => LdIVx { x },
+            0x65 => LdVxI { x },
+            0x75 if x < 8 => LdRVx { x },
+            0x85 if x < 8 => LdVxR { x },
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(instruction)
+}
+
+/// Decode `rom`, loaded at 0x200, two bytes at a time, returning the address, the opcode and the
+/// instruction, if any, of every word.
+///
+/// The address following `F000` is skipped, as it holds the address loaded into I rather than an
+/// instruction. A final odd byte is not an instruction, so it is ignored.
+pub(crate) fn decode_rom(rom: &[u8]) -> Vec<(usize, u16, Option<Instruction>)> {
+    let mut words = Vec::new();
+    let mut offset = 0;
+    while offset + 1 < rom.len() {
+        let opcode = (rom[offset] as u16) << 8 | rom[offset + 1] as u16;
+        let instruction = decode(opcode);
+        words.push((0x200 + offset, opcode, instruction));
+        if instruction == Some(Instruction::LdILong) {
+            offset += 2;
+        }
+        offset += 2;
+    }
+    words
+}
+
+/// Return the address and the opcode of every word of `rom` that `decode` does not recognise, with
+/// `rom` loaded at 0x200.
+///
+/// This is a linear scan two bytes at a time with `decode_rom`, as control flow cannot be followed
+/// without running the program, so sprites and other data in `rom` may be reported too.
+pub fn validate(rom: &[u8]) -> Vec<(usize, u16)> {
+    decode_rom(rom)
+        .into_iter()
+        .filter(|&(_, _, instruction)| instruction.is_none())
+        .map(|(address, opcode, _)| (address, opcode))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Instruction::*;
+    use super::*;
+
+    #[test]
+    fn decode_representative_opcodes() {
+        let cases = [
+            (0x00E0, Cls),
+            (0x00C5, Scd(5)),
+            (0x0123, Sys(0x123)),
+            (0x1ABC, Jump(0xABC)),
+            (0x5AB0, SeReg { x: 0xA, y: 0xB }),
+            (0x8AB6, Shr { x: 0xA, y: 0xB }),
+            (0xD12F, Drw { x: 1, y: 2, n: 0xF }),
+            (0xE3A1, Sknp { x: 3 }),
+            (0xF000, LdILong),
+            (0xF201, Plane(2)),
+            (0xF765, LdVxI { x: 7 }),
+            (0xF785, LdVxR { x: 7 }),
+        ];
+        for &(opcode, instruction) in &cases {
+            assert_eq!(decode(opcode), Some(instruction), "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn decode_rejects_illegal_opcodes() {
+        for &opcode in &[0x5AB1, 0x800F, 0x9AB1, 0xE19F, 0xF100, 0xF102, 0xF885, 0xFFFF] {
+            assert_eq!(decode(opcode), None, "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn encode_round_trips_every_opcode() {
+        for opcode in 0..=0xFFFF {
+            if let Some(instruction) = decode(opcode) {
+                assert_eq!(instruction.encode(), opcode, "{:?}", instruction);
+            }
+        }
+    }
+
+    #[test]
+    fn validate_reports_unknown_opcodes_by_address() {
+        // LD V0, 0x01; 800F; JP 0x200
+        assert_eq!(validate(&[0x60, 0x01, 0x80, 0x0F, 0x12, 0x00]), [(0x202, 0x800F)]);
+        // LD I, long 0xFFFF; 800F
+        assert_eq!(validate(&[0xF0, 0x00, 0xFF, 0xFF, 0x80, 0x0F]), [(0x204, 0x800F)]);
+    }
+}
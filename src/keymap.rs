@@ -0,0 +1,138 @@
+//! Mapping keyboard keys to keys on the CHIP-8 keypad.
+
+use glutin::VirtualKeyCode;
+use std::collections::HashMap;
+
+/// A mapping of keyboard keys to keypad indices.
+pub type Keymap = HashMap<VirtualKeyCode, usize>;
+
+// Generate `key_from_name`, which returns the key named like its `VirtualKeyCode` variant.
+macro_rules! key_names {
+    ( $( $name:ident ),* $(,)* ) => {
+        /// Return the key called `name`, which is the name of its `VirtualKeyCode` variant (e.g.
+        /// `"Q"`, `"Key1"` or `"Numpad0"`).
+        pub fn key_from_name(name: &str) -> Option<VirtualKeyCode> {
+            match name {
+                $( stringify!($name) => Some(VirtualKeyCode::$name), )*
+                _ => None,
+            }
+        }
+    };
+}
+
+key_names! {
+    Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0,
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+    Up, Down, Left, Right, Space, Return, Back,
+    Apostrophe, Comma, Minus, Period, Semicolon, Slash, Equals, LBracket, RBracket, Backslash,
+}
+
+/// Return the default mapping of the keypad onto the left side of a QWERTY keyboard.
+pub fn default_keymap() -> Keymap {
+    use glutin::VirtualKeyCode::*;
+
+    [
+        (Key1, 0x1),
+        (Key2, 0x2),
+        (Key3, 0x3),
+        (Key4, 0xC),
+        (Q, 0x4),
+        (W, 0x5),
+        (E, 0x6),
+        (R, 0xD),
+        (A, 0x7),
+        (S, 0x8),
+        (D, 0x9),
+        (F, 0xE),
+        (Z, 0xA),
+        (X, 0x0),
+        (C, 0xB),
+        (V, 0xF),
+    ]
+    .iter()
+    .cloned()
+    .collect()
+}
+
+/// The prefix of the names of gamepad buttons in a keymap file, e.g. `GamepadSouth`.
+pub const BUTTON_PREFIX: &str = "Gamepad";
+
+/// Parse a keymap file.
+///
+/// Each line maps a key, named like its `VirtualKeyCode` variant, to a keypad key in
+/// hexadecimal, e.g. `Q = 4` or `Key1 = 0x1`. Empty lines and lines starting with `#` are
+/// ignored. Keys that are not mentioned are not mapped.
+///
+/// Lines mapping gamepad buttons, whose names start with `BUTTON_PREFIX`, are skipped; see
+/// `gamepad::parse_button_map`.
+pub fn parse_keymap(text: &str) -> Result<Keymap, String> {
+    let mut keymap = Keymap::new();
+    for (number, name, index) in parse_entries(text)? {
+        if name.starts_with(BUTTON_PREFIX) {
+            continue;
+        }
+        let key = key_from_name(name)
+            .ok_or_else(|| format!("line {}: unknown key {}", number, name))?;
+        keymap.insert(key, index);
+    }
+    Ok(keymap)
+}
+
+/// Parse the lines of a keymap file into their line number, the name of the key or button and the
+/// keypad key.
+pub fn parse_entries(text: &str) -> Result<Vec<(usize, &str, usize)>, String> {
+    let mut entries = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: String| format!("line {}: {}", number + 1, message);
+        let mut parts = line.splitn(2, '=');
+        let (name, value) = match (parts.next(), parts.next()) {
+            (Some(name), Some(value)) => (name.trim(), value.trim()),
+            _ => return Err(error(String::from("expected <key> = <keypad key>"))),
+        };
+        let digits = value.trim_start_matches("0x");
+        let index = match usize::from_str_radix(digits, 16) {
+            Ok(index) if index < 16 && !digits.starts_with('+') => index,
+            _ => return Err(error(format!("invalid keypad key {}", value))),
+        };
+        entries.push((number + 1, name, index));
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_keymap_reads_valid_file() {
+        let text = "# Arrows\nUp = 0x5\n  Down = 8\n\nSpace=F\nGamepadSouth = 5\n";
+        let keymap = parse_keymap(text).unwrap();
+        assert_eq!(keymap.len(), 3);
+        assert_eq!(keymap[&VirtualKeyCode::Up], 0x5);
+        assert_eq!(keymap[&VirtualKeyCode::Down], 0x8);
+        assert_eq!(keymap[&VirtualKeyCode::Space], 0xF);
+    }
+
+    #[test]
+    fn parse_keymap_rejects_out_of_range_key() {
+        assert_eq!(
+            parse_keymap("Q = 4\nW = 10\n"),
+            Err(String::from("line 2: invalid keypad key 10"))
+        );
+        assert_eq!(parse_keymap("Q = -1"), Err(String::from("line 1: invalid keypad key -1")));
+        assert_eq!(parse_keymap("Q 4"), Err(String::from("line 1: expected <key> = <keypad key>")));
+        assert_eq!(parse_keymap("Enter = 4"), Err(String::from("line 1: unknown key Enter")));
+    }
+
+    #[test]
+    fn default_keymap_covers_keypad() {
+        let mut indices: Vec<_> = default_keymap().values().cloned().collect();
+        indices.sort();
+        assert_eq!(indices, (0..16).collect::<Vec<_>>());
+    }
+}
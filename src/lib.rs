@@ -2,16 +2,67 @@
 //!
 //! See Cowgod's [CHIP-8 technical reference](http://devernay.free.fr/hacks/chip8/C8TECH10.HTM) for
 //! a specification for the CHIP-8 processor.
+//!
+//! Without the default `std` feature, the crate is `no_std` and only needs `alloc`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
 
+#[cfg(not(feature = "std"))]
+#[macro_use]
+extern crate alloc;
+#[cfg(feature = "serde")]
+extern crate bincode;
+#[cfg(feature = "std")]
+extern crate core;
 extern crate rand;
+#[cfg(feature = "serde")]
+extern crate serde;
+
+mod assembler;
+mod disassembler;
+mod instruction;
+
+pub use assembler::assemble;
+pub use disassembler::{diff_roms, disassemble};
+pub use instruction::{decode, validate, Instruction};
 
+use instruction::decode_rom;
+
+#[cfg(not(feature = "std"))]
+use alloc::boxed::Box;
+#[cfg(not(feature = "std"))]
+use alloc::collections::{BTreeSet, VecDeque};
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use core::cell::Cell;
+use core::convert::TryFrom;
+use core::mem;
+use core::ops::Range;
 use self::rand::rngs::SmallRng;
-use self::rand::{FromEntropy, Rng};
+#[cfg(feature = "std")]
+use self::rand::FromEntropy;
+use self::rand::{Rng, SeedableRng};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
+use std::collections::{BTreeSet, VecDeque};
 
 /// The width of a CHIP-8 display.
 pub const WIDTH: usize = 64;
 /// The height of a CHIP-8 display.
 pub const HEIGHT: usize = 32;
+/// The width of a SUPER-CHIP display in high-resolution mode.
+pub const HIRES_WIDTH: usize = 128;
+/// The height of a SUPER-CHIP display in high-resolution mode.
+pub const HIRES_HEIGHT: usize = 64;
+/// The size of the CHIP-8 memory.
+pub const MEMORY_SIZE: usize = 0x1000;
+/// The size of the memory in XO-CHIP mode.
+pub const XOCHIP_MEMORY_SIZE: usize = 0x10000;
+/// The number of instructions `Processor::run_for` executes per tick of the timers.
+const HEADLESS_CYCLES_PER_FRAME: usize = 12;
 /// The CHIP-8 font for characters 0-9 and A-F.
 pub const FONTSET: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -31,11 +82,113 @@ pub const FONTSET: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
+/// The address in memory of the SUPER-CHIP high-resolution font, directly after `FONTSET`.
+pub const FONTSET_HIGH_ADDRESS: usize = 0x50;
+/// The SUPER-CHIP 8x10 font for characters 0-9 and A-F.
+pub const FONTSET_HIGH: [u8; 160] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0x3C, 0x7E, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFE, 0xC3, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xFE, 0xFC, // B
+    0x3C, 0x7E, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0x7E, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+/// Return the hexadecimal label ('0'-'9', 'A'-'F') of the key at `index` on the keypad, or `None`
+/// if `index` is not a key on the keypad, i.e. if it is greater than 0xF.
+pub fn keypad_label(index: usize) -> Option<char> {
+    if index >= 16 {
+        return None;
+    }
+    ::core::char::from_digit(index as u32, 16).map(|label| label.to_ascii_uppercase())
+}
+
+/// Return the index of the key on the keypad labelled `label`, ignoring case.
+pub fn keypad_index(label: char) -> Option<usize> {
+    label.to_digit(16).map(|digit| digit as usize)
+}
+
+/// Return the names of the ROMs built into the crate.
+///
+/// These can be loaded with `Processor::load_builtin`.
+pub fn builtin_roms() -> &'static [&'static str] {
+    &["font", "keypad"]
+}
+
+/// Return the contents of the built-in ROM called `name`.
+fn builtin_rom(name: &str) -> Option<&'static [u8]> {
+    match name {
+        // Draws the 16 hexadecimal digits of the font.
+        "font" => Some(include_bytes!("../roms/font.ch8")),
+        // Draws the hexadecimal digit of the last key pressed.
+        "keypad" => Some(include_bytes!("../roms/keypad.ch8")),
+        _ => None,
+    }
+}
 
-/// The `Error` type returned when an error occurred in `Processor::run_cycle`.
+/// The `Error` type returned when an operation on a `Processor` fails.
 pub enum Error {
     /// A `String` error.
     Error(String),
+    /// An unknown opcode was executed.
+    UnknownOpcode {
+        /// The address of the opcode.
+        pc: usize,
+        /// The opcode.
+        opcode: u16,
+    },
+    /// An instruction was executed at an odd address, under the `strict_alignment` quirk.
+    MisalignedProgramCounter {
+        /// The address of the instruction.
+        pc: usize,
+    },
+    /// A `2nnn` CALL was executed with a full stack.
+    StackOverflow {
+        /// The address of the CALL.
+        pc: usize,
+    },
+    /// A `00EE` RET was executed with an empty stack.
+    StackUnderflow {
+        /// The address of the RET.
+        pc: usize,
+    },
+    /// An address outside of memory was accessed.
+    MemoryOutOfBounds {
+        /// The address.
+        addr: usize,
+    },
+    /// An instruction wrote below 0x200, under the `protect_interpreter_region` quirk.
+    ProtectedMemoryWrite {
+        /// The address written to.
+        addr: usize,
+    },
+    /// A ROM did not fit in memory.
+    RomTooLarge {
+        /// The size of the ROM in bytes.
+        size: usize,
+        /// The maximum size of a ROM in bytes.
+        max_size: usize,
+    },
+    /// A built-in ROM that does not exist was requested.
+    UnknownBuiltinRom {
+        /// The name of the requested ROM.
+        name: String,
+    },
+    /// A save state could not be loaded.
+    InvalidSaveState {
+        /// Why the save state is invalid.
+        reason: String,
+    },
 }
 
 impl From<String> for Error {
@@ -44,53 +197,511 @@ impl From<String> for Error {
     }
 }
 
-impl ::std::fmt::Display for Error {
-    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+impl ::core::fmt::Display for Error {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
         match self {
             Error::Error(e) => write!(f, "{}", e),
+            Error::UnknownOpcode { pc, opcode } => {
+                write!(f, "Unknown opcode at 0x{:X}: 0x{:04X}.", pc, opcode)
+            }
+            Error::MisalignedProgramCounter { pc } => {
+                write!(f, "Misaligned program counter: 0x{:X}.", pc)
+            }
+            Error::StackOverflow { pc } => write!(f, "Stack overflow at 0x{:X}.", pc),
+            Error::StackUnderflow { pc } => write!(f, "Stack underflow at 0x{:X}.", pc),
+            Error::MemoryOutOfBounds { addr } => write!(f, "Address out of bounds: 0x{:X}.", addr),
+            Error::ProtectedMemoryWrite { addr } => {
+                write!(f, "Write to protected memory at 0x{:X}.", addr)
+            }
+            Error::RomTooLarge { size, max_size } => write!(
+                f,
+                "ROM too large: {} bytes (maximum is {} bytes).",
+                size, max_size
+            ),
+            Error::UnknownBuiltinRom { name } => write!(f, "Unknown built-in ROM: {}.", name),
+            Error::InvalidSaveState { reason } => write!(f, "Invalid save state: {}.", reason),
         }
     }
 }
 
-impl ::std::fmt::Debug for Error {
-    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
-        match self {
-            Error::Error(e) => write!(f, "{}", e),
-        }
+impl ::core::fmt::Debug for Error {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        write!(f, "{}", self)
     }
 }
 
+#[cfg(feature = "std")]
 impl ::std::error::Error for Error {
     fn description(&self) -> &str {
         match self {
-            Error::Error(e) => &e,
+            Error::Error(e) => e,
+            Error::UnknownOpcode { .. } => "unknown opcode",
+            Error::MisalignedProgramCounter { .. } => "misaligned program counter",
+            Error::StackOverflow { .. } => "stack overflow",
+            Error::StackUnderflow { .. } => "stack underflow",
+            Error::MemoryOutOfBounds { .. } => "address out of bounds",
+            Error::ProtectedMemoryWrite { .. } => "write to protected memory",
+            Error::RomTooLarge { .. } => "ROM too large",
+            Error::UnknownBuiltinRom { .. } => "unknown built-in ROM",
+            Error::InvalidSaveState { .. } => "invalid save state",
+        }
+    }
+}
+
+/// Configurable deviations from the default behaviour of the processor.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Quirks {
+    /// Refuse to execute an instruction at an odd address.
+    ///
+    /// Every CHIP-8 instruction is two bytes long, so a program counter pointing at an odd address
+    /// usually means that a ROM jumped to a misaligned address. When enabled, `step` returns
+    /// an error instead of executing the instruction.
+    pub strict_alignment: bool,
+    /// Refuse writes to the interpreter region (below 0x200).
+    ///
+    /// The interpreter and the font traditionally live below 0x200, so ROMs should not write
+    /// there. When enabled, instructions that write to memory below 0x200 return an error instead
+    /// of writing. The font can still be loaded.
+    pub protect_interpreter_region: bool,
+    /// Reject opcodes whose fixed bits do not match their encoding.
+    ///
+    /// For example, `5xy0` and `9xy0` require the last nibble to be zero. When enabled, an opcode
+    /// such as `5xy1` is reported as unknown; otherwise the last nibble is ignored.
+    pub strict_encoding: bool,
+    /// Skip unknown opcodes as if they were no-ops, instead of returning an error.
+    ///
+    /// Some ROMs execute data or instructions of other interpreters that are never meant to do
+    /// anything. Opcodes rejected by `strict_encoding` are still reported.
+    pub skip_unknown_opcodes: bool,
+    /// Shift Vy instead of Vx in `8xy6` and `8xyE`, storing the result in Vx.
+    ///
+    /// This is the behaviour of the original COSMAC VIP interpreter; CHIP-48 and SUPER-CHIP shift
+    /// Vx in place.
+    pub shift_uses_vy: bool,
+    /// How `Fx55` and `Fx65` change I after copying the registers.
+    pub index_increment: IndexIncrement,
+    /// Interpret `Bnnn` as `BXNN`, jumping to XNN plus Vx instead of nnn plus V0.
+    ///
+    /// This is the behaviour of the CHIP-48 and SUPER-CHIP interpreters.
+    pub jump_uses_vx: bool,
+    /// Make `Fx1E` set VF to 1 when I overflows past 0xFFF, and to 0 otherwise.
+    ///
+    /// This is the behaviour of the Amiga interpreter, which at least Spacefight 2091! relies on.
+    pub i_overflow_sets_vf: bool,
+    /// Clip sprites at the edges of the display in `Dxyn`, instead of wrapping them around.
+    ///
+    /// The starting coordinates still wrap around; only the pixels extending past the right or
+    /// bottom edge are clipped.
+    pub sprite_clipping: bool,
+    /// Make `Dxyn` wait for the next vertical blank, i.e. the next time the timers tick.
+    ///
+    /// The original COSMAC VIP interpreter did this, which limits drawing to 60 sprites per second.
+    /// While waiting, `step` does not execute any instructions.
+    pub display_wait: bool,
+    /// Make `Fx0A` wait for the pressed key to be released before storing it in Vx.
+    ///
+    /// This is the behaviour of the original COSMAC VIP interpreter, and keeps a single key press
+    /// from being read by several `Fx0A`s in a row.
+    pub wait_for_key_release: bool,
+    /// Make `8xy1`, `8xy2` and `8xy3` reset VF to 0.
+    ///
+    /// This is a side effect of how the original COSMAC VIP interpreter implemented the logic
+    /// instructions, which some test ROMs check for.
+    pub logic_resets_vf: bool,
+}
+
+/// How `Fx55` and `Fx65` change I after copying registers V0 through Vx.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum IndexIncrement {
+    /// Leave I unchanged, as modern interpreters do.
+    #[default]
+    None,
+    /// Increment I by x, as the CHIP-48 interpreter does.
+    ByX,
+    /// Increment I by x + 1, as the original COSMAC VIP interpreter does.
+    ByXPlus1,
+}
+
+/// How `Dxyn` combines a sprite with the display.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum DrawMode {
+    /// XOR the sprite onto the display and report collisions in VF, as the hardware does.
+    #[default]
+    Xor,
+    /// OR the sprite onto the display without collision detection.
+    ///
+    /// This never clears a pixel, which is useful for building sprites in a debugger or editor.
+    Or,
+}
+
+/// When changes to the display request a present.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum PresentMode {
+    /// Request a present whenever the display changes, i.e. on every `00E0` and `Dxyn`.
+    ///
+    /// A frame made up of several sprites may be presented before it is complete, which causes
+    /// flicker.
+    Immediate,
+    /// Request a present when the timers tick, if the display changed since the last tick.
+    ///
+    /// This presents at most once per frame, which reduces flicker at the cost of up to a frame of
+    /// latency.
+    #[default]
+    PerFrame,
+    /// Only request a present when `Processor::present` is called.
+    ///
+    /// This gives the host full control, but the host must decide when a frame is complete.
+    Manual,
+}
+
+/// The configuration of a `Processor`, see `Processor::with_config`.
+///
+/// The presets `chip8`, `schip` and `xochip` set the quirks that programs written for each
+/// platform expect. The default configuration matches `Processor::new`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProcessorConfig {
+    /// The quirks the processor emulates.
+    pub quirks: Quirks,
+    /// Whether the XO-CHIP extensions are enabled.
+    pub xochip: bool,
+    /// How sprites are drawn onto the display.
+    pub draw_mode: DrawMode,
+    /// When changes to the display request a present.
+    pub present_mode: PresentMode,
+    /// Whether keys only become visible to instructions when the timers tick.
+    pub poll_keypad: bool,
+    /// The seed for the random number generator, or `None` to seed it from the system (or with a
+    /// fixed seed without `std`).
+    pub rng_seed: Option<u64>,
+}
+
+impl ProcessorConfig {
+    /// The configuration of the original COSMAC VIP CHIP-8 interpreter.
+    pub fn chip8() -> ProcessorConfig {
+        ProcessorConfig {
+            quirks: Quirks {
+                shift_uses_vy: true,
+                index_increment: IndexIncrement::ByXPlus1,
+                sprite_clipping: true,
+                display_wait: true,
+                wait_for_key_release: true,
+                logic_resets_vf: true,
+                ..Quirks::default()
+            },
+            ..ProcessorConfig::default()
+        }
+    }
+
+    /// The configuration of the SUPER-CHIP 1.1 interpreter.
+    pub fn schip() -> ProcessorConfig {
+        ProcessorConfig {
+            quirks: Quirks {
+                jump_uses_vx: true,
+                sprite_clipping: true,
+                ..Quirks::default()
+            },
+            ..ProcessorConfig::default()
+        }
+    }
+
+    /// The configuration of XO-CHIP, as implemented by Octo.
+    pub fn xochip() -> ProcessorConfig {
+        ProcessorConfig {
+            quirks: Quirks {
+                shift_uses_vy: true,
+                index_increment: IndexIncrement::ByXPlus1,
+                ..Quirks::default()
+            },
+            xochip: true,
+            ..ProcessorConfig::default()
+        }
+    }
+
+    /// Create a `Processor` with this configuration.
+    pub fn build(self) -> Processor {
+        Processor::with_config(self)
+    }
+}
+
+/// A rectangular region of the display.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Region {
+    /// The x coordinate of the left column.
+    pub x: usize,
+    /// The y coordinate of the top row.
+    pub y: usize,
+    /// The number of columns.
+    pub width: usize,
+    /// The number of rows.
+    pub height: usize,
+}
+
+impl Region {
+    /// Return the smallest region containing both `self` and the pixel at (`x`, `y`).
+    fn including(self, x: usize, y: usize) -> Region {
+        let left = self.x.min(x);
+        let top = self.y.min(y);
+        let right = (self.x + self.width).max(x + 1);
+        let bottom = (self.y + self.height).max(y + 1);
+        Region {
+            x: left,
+            y: top,
+            width: right - left,
+            height: bottom - top,
         }
     }
 }
 
+/// Whether memory was read or written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum MemoryAccessKind {
+    /// The memory was read.
+    Read,
+    /// The memory was written.
+    Write,
+}
+
+/// A memory access made by an instruction, as logged when `Processor::log_memory_access` is set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MemoryAccess {
+    /// Whether the memory was read or written.
+    pub kind: MemoryAccessKind,
+    /// The address that was accessed.
+    pub address: usize,
+    /// The value that was read or written.
+    pub value: u8,
+    /// The opcode of the instruction that accessed the memory.
+    pub opcode: u16,
+}
+
+/// The number of instructions executed by class, as counted when `Processor::profile_opcodes` is
+/// set.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OpcodeStats {
+    /// The number of instructions executed by their first nibble, e.g. `classes[0x1]` counts the
+    /// `1nnn` jumps.
+    pub classes: [u64; 16],
+}
+
+impl OpcodeStats {
+    /// Return the total number of instructions counted.
+    pub fn total(&self) -> u64 {
+        self.classes.iter().sum()
+    }
+}
+
+/// What happened during a call to `Processor::step`, for the host to react to.
+///
+/// When several things happen at once, the first in the order of the variants below is reported,
+/// except for `None`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CycleEvent {
+    /// Nothing that the host needs to react to.
+    None,
+    /// The program counter reached the breakpoint at the contained address, and the instruction
+    /// there was not executed.
+    Breakpoint(usize),
+    /// The program has exited with `00FD`.
+    Halted,
+    /// The buzzer started sounding, see `Processor::should_beep`.
+    BeepStart,
+    /// The buzzer stopped sounding.
+    BeepStop,
+    /// The display changed. Whether it should be presented now depends on the `present_mode`;
+    /// see `Processor::needs_present`.
+    Draw,
+}
+
+/// A snapshot of the state of the emulated machine, as returned by `Processor::state`.
+///
+/// Unlike a `Processor`, this can be compared, e.g. to check that two runs with the same seed and
+/// inputs ended up in the same state. It leaves out the random number generator, the settings
+/// chosen by the host, such as the quirks, and the debugging aids.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ProcessorState {
+    /// The memory.
+    pub memory: Vec<u8>,
+    /// The registers V0 through VF.
+    pub registers: [u8; 16],
+    /// The index register I.
+    pub index: usize,
+    /// The address of the next instruction.
+    pub program_counter: usize,
+    /// The first plane of the display, row by row.
+    pub display: Vec<bool>,
+    /// The second plane of the display, used in XO-CHIP mode.
+    pub second_plane: Vec<bool>,
+    /// Whether the XO-CHIP extensions are enabled.
+    pub xochip: bool,
+    /// The bit-planes selected with `Fn01`.
+    pub selected_planes: u8,
+    /// Whether the display is in the high-resolution mode.
+    pub hires: bool,
+    /// Whether the program exited with `00FD`.
+    pub halted: bool,
+    /// Whether the processor waits for the timers to tick after a `Dxyn`.
+    pub waiting_for_vblank: bool,
+    /// The delay timer.
+    pub delay_timer: u8,
+    /// The sound timer.
+    pub sound_timer: u8,
+    /// The XO-CHIP audio pattern.
+    pub audio_buffer: [u8; 16],
+    /// The stack.
+    pub stack: [u16; 16],
+    /// The number of addresses on the stack.
+    pub stack_pointer: usize,
+    /// The RPL user flags.
+    pub rpl_flags: [u8; 8],
+    /// The keys visible to instructions.
+    pub keypad: [bool; 16],
+    /// The keys set by the host, under `poll_keypad`.
+    pub pending_keypad: [bool; 16],
+    /// The key an `Fx0A` waits to be released, under the `wait_for_key_release` quirk.
+    pub awaited_key: Option<usize>,
+    /// The number of instructions executed since the last reset.
+    pub cycle_count: u64,
+}
+
+/// The input of a session, recorded with `Processor::record_input` and replayed with
+/// `Processor::play_input`.
+///
+/// Together with the ROM, this is all that is needed to replay a session exactly. With the `serde`
+/// feature, it can be saved alongside the ROM.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct InputLog {
+    /// The seed the RNG was reseeded with when recording started.
+    pub seed: u64,
+    /// The changes to the keys, as pairs of the number of instructions executed since the last
+    /// reset and the mask of the keys as given to `Processor::set_keys`.
+    pub keys: Vec<(usize, u16)>,
+    /// The random bytes generated by `Cxkk`, in order.
+    pub random_bytes: Vec<u8>,
+}
+
+/// A callback set by the host.
+///
+/// Closures cannot be cloned, so a clone has no callback.
+struct Callback<F: ?Sized>(Option<Box<F>>);
+
+impl<F: ?Sized> Clone for Callback<F> {
+    fn clone(&self) -> Callback<F> {
+        Callback(None)
+    }
+}
+
+impl<F: ?Sized> Default for Callback<F> {
+    fn default() -> Callback<F> {
+        Callback(None)
+    }
+}
+
 /// The CHIP-8 processor.
+///
+/// # Timing
+/// There are three rates to keep apart when driving a `Processor`:
+///
+/// - The delay and sound timers count down at 60 Hz.
+/// - The processor has no fixed clock speed; most programs expect roughly 500 to 1000
+///   instructions per second. Use `Processor::hz_to_cycles_per_frame` to convert a clock speed
+///   into a number of instructions to execute per 60 Hz frame.
+/// - The display is presented when `needs_present` returns `true`, which depends on the
+///   `present_mode`.
+///
+/// `step` executes a single instruction and `tick_timers` ticks the timers once, so a host
+/// should call `tick_timers` 60 times per second and `step` at the clock speed. Coupling the
+/// clock speed to the refresh rate of the host's display makes programs run too slowly or too
+/// quickly.
+///
+/// With the `serde` feature, a processor can be saved and restored with `save_state` and
+/// `load_state`.
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Processor {
     /// The processor's memory.
-    pub memory: [u8; 4096],
+    ///
+    /// Its length is `MEMORY_SIZE`, or `XOCHIP_MEMORY_SIZE` in XO-CHIP mode.
+    pub memory: Vec<u8>,
     /// The registers.
+    ///
+    /// VF doubles as a flag register: `8xy4`, `8xy5`, `8xy6`, `8xy7`, `8xyE` and `Dxyn` always
+    /// overwrite it with their flag, regardless of its previous value and even when it is the
+    /// destination register, so programs should not use it for general storage. Under the
+    /// `logic_resets_vf` quirk, `8xy1`, `8xy2` and `8xy3` reset it too.
     pub registers: [u8; 16],
     /// The index, which points at an element of memory.
     pub index: usize,
     /// The index in the memory which points to the current opcode.
     pub program_counter: usize,
-    /// The display.
-    pub display: [bool; WIDTH * HEIGHT],
+    /// The display, one bitset per row with the leftmost pixel in the most significant bit, like
+    /// a sprite. Read it with `get_pixel`, `lit_pixels` or `framebuffer`.
+    ///
+    /// Its length is always `height()`, so it is resized when the resolution changes. Only the
+    /// `width()` most significant bits of each row are used. In XO-CHIP mode, this is the first of
+    /// the two bit-planes.
+    display: Vec<u128>,
+    /// The second bit-plane of the display, only drawn to in XO-CHIP mode. See `pixel_colour`.
+    second_plane: Vec<u128>,
+    /// Whether the XO-CHIP extensions are enabled.
+    xochip: bool,
+    /// The font used by `Fx29`, see `set_fontset`.
+    font: Vec<u8>,
+    /// The address of `font` in memory.
+    font_address: usize,
+    /// The size of the ROM last loaded with `load_file`.
+    rom_size: usize,
+    /// The bit-planes affected by drawing, clearing and scrolling in XO-CHIP mode, as a bit mask:
+    /// bit 0 for `display` and bit 1 for the second plane.
+    pub selected_planes: u8,
+    /// Whether the display is in SUPER-CHIP's high-resolution mode.
+    hires: bool,
     /// Whether to update the display.
+    ///
+    /// This is `true` for a new processor, so that the initial blank display is presented. When it
+    /// is set after the display changes depends on the `present_mode`.
     pub draw: bool,
+    /// When changes to the display request a present.
+    pub present_mode: PresentMode,
+    /// Whether the display changed without requesting a present yet.
+    pending_present: bool,
+    /// The region of the display changed since the last present.
+    dirty_region: Option<Region>,
+    /// Whether the processor is stalled until the timers tick, under the `display_wait` quirk.
+    waiting_for_vblank: bool,
+    /// Whether the program exited with `00FD`.
+    halted: bool,
+    /// Whether the last instruction executed was a `1nnn` jumping to itself.
+    idle: bool,
+    /// How sprites are drawn onto the display.
+    pub draw_mode: DrawMode,
     /// The delay timer.
     pub delay_timer: u8,
     /// The sound timer.
     pub sound_timer: u8,
+    /// The frequency in Hz of the square wave generated by `fill_audio`.
+    pub beep_frequency: f32,
+    /// The position within a period of the square wave generated by `fill_audio`, from 0 to 1.
+    audio_phase: Cell<f32>,
+    /// The XO-CHIP audio pattern loaded with `F002`, 128 one-bit samples.
+    audio_buffer: [u8; 16],
     /// The stack.
     pub stack: [u16; 16],
     /// The index which points at the top of the stack.
     pub stack_pointer: usize,
+    /// The HP48 RPL user flags, saved and restored by the SUPER-CHIP instructions Fx75 and Fx85.
+    ///
+    /// Like on the HP48, the flags survive a `reset`.
+    pub rpl_flags: [u8; 8],
     /// Keypad with 16 keys which can be pressed (`true`) or not (`false`).
     ///
     /// # Example mapping
@@ -107,8 +718,78 @@ pub struct Processor {
     /// +-+-+-+-+      +-+-+-+-+
     /// ```
     pub keypad: [bool; 16],
+    /// Whether keys set with `set_key` only become visible to instructions when the timers tick.
+    ///
+    /// The original hardware polled the keypad at the display rate, and some games are sensitive
+    /// to reading input faster than that. When disabled, `set_key` updates `keypad` immediately.
+    pub poll_keypad: bool,
+    /// The keys set with `set_key`, copied to `keypad` when the timers tick if `poll_keypad` is
+    /// set.
+    pending_keypad: [bool; 16],
+    /// The quirks the processor emulates.
+    pub quirks: Quirks,
+    /// Whether to log the memory reads and writes made by instructions.
+    ///
+    /// This reveals data tables and self-modifying code when reverse-engineering ROMs. The log can
+    /// be retrieved with `take_memory_log`.
+    pub log_memory_access: bool,
+    /// The logged memory accesses.
+    memory_log: Vec<MemoryAccess>,
+    /// Whether to count the executed instructions by class in `opcode_stats`.
+    ///
+    /// This shows which instructions a ROM spends its time in.
+    pub profile_opcodes: bool,
+    /// The executed instructions counted while `profile_opcodes` is set.
+    opcode_stats: OpcodeStats,
+    /// The key pressed during an `Fx0A` that waits for its release, under the
+    /// `wait_for_key_release` quirk.
+    awaited_key: Option<usize>,
+    /// The number of instructions executed since the last reset, see `cycles`.
+    cycle_count: u64,
+    /// Whether the input is recorded in `input_log`.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    recording_input: bool,
+    /// The recorded input.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    input_log: InputLog,
+    /// The input being replayed.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    playback: InputLog,
+    /// The index of the next change to the keys to replay in `playback`.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    playback_position: usize,
+    /// The index of the next random byte to replay in `playback`.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    random_position: usize,
     /// The random number generator (RNG).
+    #[cfg_attr(feature = "serde", serde(skip, default = "SmallRng::from_entropy"))]
     rng: SmallRng,
+    /// The addresses at which execution stops, see `add_breakpoint`.
+    breakpoints: BTreeSet<usize>,
+    /// The breakpoint that `step` last stopped at, which is passed on the next call.
+    stopped_at_breakpoint: Option<usize>,
+    /// Whether to save a snapshot of the processor before each instruction, so that it can be
+    /// undone with `step_back`.
+    ///
+    /// Each snapshot is a copy of the processor, so this is mostly useful for debugging.
+    pub record_history: bool,
+    /// The maximum number of snapshots kept while `record_history` is set. The oldest snapshots
+    /// are dropped first. Defaults to 1000.
+    pub history_limit: usize,
+    /// The snapshots taken while `record_history` is set, the most recent last.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    history: VecDeque<Processor>,
+    /// Whether the beep callback was last called with `true`.
+    beeping: bool,
+    /// Whether the display changed during the current call to `step`.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    changed_display: bool,
+    /// The function called when `should_beep` changes.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    beep_callback: Callback<dyn FnMut(bool)>,
+    /// The function called before each instruction is executed.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    trace_callback: Callback<dyn FnMut(usize, u16)>,
 }
 
 impl Processor {
@@ -117,109 +798,858 @@ impl Processor {
         Processor::default()
     }
 
+    /// Create a new `Processor` whose random number generator is seeded with `seed`.
+    ///
+    /// Running the same program with the same seed and inputs produces the same results, which is
+    /// useful for testing and for replaying sessions.
+    pub fn with_seed(seed: u64) -> Processor {
+        Processor {
+            rng: seeded_rng(seed),
+            ..Processor::default()
+        }
+    }
+
+    /// Create a new `Processor` with the configuration `config`.
+    pub fn with_config(config: ProcessorConfig) -> Processor {
+        let mut processor = match config.rng_seed {
+            Some(seed) => Processor::with_seed(seed),
+            None => Processor::new(),
+        };
+        processor.quirks = config.quirks;
+        processor.set_xochip(config.xochip);
+        processor.draw_mode = config.draw_mode;
+        processor.present_mode = config.present_mode;
+        processor.poll_keypad = config.poll_keypad;
+        processor
+    }
+
+    /// Return the number of instructions to execute per 60 Hz frame to run at a clock speed of
+    /// `hz`, rounded to the nearest integer.
+    pub fn hz_to_cycles_per_frame(hz: u32) -> u32 {
+        hz / 60 + u32::from(hz % 60 >= 30)
+    }
+
     /// Create a new `Processor` and load `file` into memory.
+    ///
+    /// # Panics
+    /// Panics if `file` does not fit in memory. Use `Processor::try_from` to handle oversized files
+    /// gracefully.
     pub fn with_file(file: &[u8]) -> Processor {
         let mut processor = Processor::default();
-        processor.load_file(file);
+        processor.load_file(file).unwrap();
         processor
     }
 
-    /// Load `file` into memory.
-    pub fn load_file(&mut self, file: &[u8]) {
-        self.memory[0x200..0x200 + file.len()].copy_from_slice(&file);
+    /// Load `file` into memory, returning an error if it does not fit.
+    pub fn load_file(&mut self, file: &[u8]) -> Result<(), Error> {
+        let max_size = self.memory.len() - 0x200;
+        if file.len() > max_size {
+            return Err(Error::RomTooLarge {
+                size: file.len(),
+                max_size,
+            });
+        }
+        self.memory[0x200..0x200 + file.len()].copy_from_slice(file);
+        self.rom_size = file.len();
+        Ok(())
+    }
+
+    /// Replace the font used by `Fx29` with `font`, the sprites of the digits 0-F of 5 bytes
+    /// each, stored at `address`.
+    ///
+    /// The font must fit below 0x200, where programs are loaded. It may overlap `FONTSET_HIGH`,
+    /// in which case it replaces part of the high-resolution font. Like the default font, it is
+    /// copied into memory again by `reset`.
+    pub fn set_fontset(&mut self, font: &[u8], address: usize) -> Result<(), Error> {
+        let end = match address.checked_add(font.len()) {
+            Some(end) if end <= 0x200 => end,
+            Some(end) => return Err(Error::MemoryOutOfBounds { addr: end - 1 }),
+            None => return Err(Error::MemoryOutOfBounds { addr: address }),
+        };
+        self.memory[address..end].copy_from_slice(font);
+        self.font = font.to_vec();
+        self.font_address = address;
+        Ok(())
+    }
+
+    /// Restore the processor to its initial state, keeping the loaded ROM and the configuration.
+    ///
+    /// The fonts are copied into memory again, in case a program overwrote them.
+    pub fn reset(&mut self) {
+        self.memory[FONTSET_HIGH_ADDRESS..FONTSET_HIGH_ADDRESS + FONTSET_HIGH.len()]
+            .copy_from_slice(&FONTSET_HIGH);
+        self.memory[self.font_address..self.font_address + self.font.len()]
+            .copy_from_slice(&self.font);
+        self.registers = [0; 16];
+        self.index = 0;
+        self.program_counter = 0x200;
+        self.set_resolution(false);
+        self.selected_planes = 0b01;
+        self.draw = true;
+        self.pending_present = false;
+        self.dirty_region = None;
+        self.waiting_for_vblank = false;
+        self.halted = false;
+        self.idle = false;
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.audio_buffer = [0; 16];
+        self.stack = [0; 16];
+        self.stack_pointer = 0;
+        self.keypad = [false; 16];
+        self.pending_keypad = [false; 16];
+        self.memory_log.clear();
+        self.opcode_stats = OpcodeStats::default();
+        self.awaited_key = None;
+        self.cycle_count = 0;
+        self.stopped_at_breakpoint = None;
+        self.update_beep();
+    }
+
+    /// Restore the processor to its initial state like `reset`, and replace the loaded ROM with
+    /// `file`.
+    pub fn reset_and_reload(&mut self, file: &[u8]) -> Result<(), Error> {
+        self.reset();
+        for byte in &mut self.memory[0x200..] {
+            *byte = 0;
+        }
+        self.load_file(file)
+    }
+
+    /// Save the state of the processor, including memory, in a compact binary format.
+    ///
+    /// The callbacks and the state of the random number generator are not saved.
+    #[cfg(feature = "serde")]
+    pub fn save_state(&self) -> Vec<u8> {
+        // Serialising into a `Vec` only fails for types that serde cannot represent.
+        bincode::serialize(self).expect("a processor can always be serialised")
+    }
+
+    /// Restore a state saved with `save_state`.
+    ///
+    /// The callbacks and the random number generator are kept. If the buzzer starts or stops
+    /// sounding, the beep callback is called.
+    #[cfg(feature = "serde")]
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let state: Processor = bincode::deserialize(bytes).map_err(|e| Error::InvalidSaveState {
+            reason: e.to_string(),
+        })?;
+        let beeping = self.beeping;
+        *self = Processor {
+            rng: self.rng.clone(),
+            beeping,
+            beep_callback: mem::take(&mut self.beep_callback),
+            trace_callback: mem::take(&mut self.trace_callback),
+            ..state
+        };
+        self.update_beep();
+        Ok(())
+    }
+
+    /// Load the built-in ROM called `name` into memory.
+    ///
+    /// See `builtin_roms` for the available names.
+    pub fn load_builtin(&mut self, name: &str) -> Result<(), Error> {
+        match builtin_rom(name) {
+            Some(file) => self.load_file(file),
+            None => Err(Error::UnknownBuiltinRom {
+                name: String::from(name),
+            }),
+        }
     }
 
     /// Set the state of a key.
+    ///
+    /// If `poll_keypad` is set, the key only becomes visible to instructions when the timers tick.
     pub fn set_key(&mut self, key: usize, pressed: bool) {
-        self.keypad[key] = pressed;
+        self.write_key(key, pressed);
+        self.record_keys();
     }
 
-    /// Get the current `opcode`.
-    pub fn opcode(&self) -> u16 {
-        (self.memory[self.program_counter] as u16) << 8
-            | self.memory[self.program_counter + 1] as u16
+    /// Set the state of all keys from `mask`, in which bit `n` is set if key `n` is pressed.
+    ///
+    /// Like `set_key`, this respects `poll_keypad`.
+    pub fn set_keys(&mut self, mask: u16) {
+        for key in 0..16 {
+            self.write_key(key, mask & (1 << key) != 0);
+        }
+        self.record_keys();
     }
 
-    /// Emulate a processor cycle.
-    pub fn run_cycle(&mut self) -> Result<(), Error> {
-        // V![$index] is the register at $index.
-        macro_rules! V {
-            [ $index:expr ] => { self.registers[$index] };
+    /// Start recording the input into the log returned by `input_log`, or stop if already
+    /// recording.
+    ///
+    /// Starting clears the log, reseeds the RNG with a seed drawn from it and records that seed,
+    /// then records the current keys. While recording, the changes to the keys made with `set_key`
+    /// and `set_keys` and the random bytes generated by `Cxkk` are recorded.
+    pub fn record_input(&mut self) {
+        self.recording_input = !self.recording_input;
+        if self.recording_input {
+            let seed = self.rng.gen();
+            self.rng = seeded_rng(seed);
+            self.input_log = InputLog {
+                seed,
+                ..InputLog::default()
+            };
+            self.record_keys();
         }
+    }
 
-        let opcode = self.opcode();
+    /// Return the input recorded with `record_input`.
+    pub fn input_log(&self) -> &InputLog {
+        &self.input_log
+    }
 
-        self.program_counter += 2;
+    /// Replay `log`, as recorded with `record_input`.
+    ///
+    /// The RNG is reseeded with the seed of the log, and `Cxkk` uses the random bytes of the log
+    /// until they run out. The keys are set as each change comes due in `step`. This reproduces a
+    /// recorded session from a reset, provided the timers are ticked at the same points as while
+    /// recording. As the random bytes are replayed as they were generated, this holds even if the
+    /// RNG algorithm changed since the session was recorded.
+    pub fn play_input(&mut self, log: &InputLog) {
+        self.rng = seeded_rng(log.seed);
+        self.playback = log.clone();
+        self.playback_position = 0;
+        self.random_position = 0;
+    }
 
-        let x: usize = (opcode as usize & 0x0F00) >> 8;
-        let y: usize = (opcode as usize & 0x00F0) >> 4;
-        let n: u8 = opcode as u8 & 0x000F;
-        let kk: u8 = opcode as u8;
-        let nnn: usize = opcode as usize & 0x0FFF;
+    /// Return the state of all keys as a mask, in which bit `n` is set if key `n` is pressed.
+    pub fn keys(&self) -> u16 {
+        self.keypad
+            .iter()
+            .enumerate()
+            .fold(0, |mask, (key, &pressed)| mask | (pressed as u16) << key)
+    }
 
-        match (opcode & 0xF000) >> 12 {
-            0x0 => match opcode & 0x00FF {
-                // 00E0 - CLS
-                // Clear the display.
-                0xE0 => {
-                    self.display = [false; WIDTH * HEIGHT];
-                    self.draw = true;
-                }
-                // 00EE - RET
-                // Return from a subroutine.
-                // The interpreter sets the program counter to the address at the top of the stack,
-                // then subtracts 1 from the stack pointer.
-                0xEE => {
-                    self.stack_pointer -= 1;
-                    self.program_counter = self.stack[self.stack_pointer] as usize;
+    /// Return the width of the display in the active resolution.
+    pub fn width(&self) -> usize {
+        if self.hires {
+            HIRES_WIDTH
+        } else {
+            WIDTH
+        }
+    }
+
+    /// Return the height of the display in the active resolution.
+    pub fn height(&self) -> usize {
+        if self.hires {
+            HIRES_HEIGHT
+        } else {
+            HEIGHT
+        }
+    }
+
+    /// Return whether the display is in SUPER-CHIP's high-resolution mode.
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    /// Return the width, the height and the pixels of the first plane of the display, row by
+    /// row.
+    pub fn framebuffer(&self) -> (usize, usize, Vec<bool>) {
+        (self.width(), self.height(), self.pixels(&self.display))
+    }
+
+    /// Return an iterator over the coordinates (`x`, `y`) of the pixels that are set in the first
+    /// plane of the display, row by row.
+    pub fn lit_pixels(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let width = self.width();
+        self.display.iter().enumerate().flat_map(move |(y, &row)| {
+            (0..width)
+                .filter(move |&x| row & pixel_bit(x) != 0)
+                .map(move |x| (x, y))
+        })
+    }
+
+    /// Return the indices into the pixels returned by `framebuffer` that differ from `previous`, a
+    /// copy of them kept by the host, in increasing order.
+    ///
+    /// If the resolution changed, so that the lengths differ, every index is returned. See also
+    /// `dirty_region`, which the processor tracks itself.
+    pub fn display_diff(&self, previous: &[bool]) -> Vec<usize> {
+        let (width, height) = (self.width(), self.height());
+        if previous.len() != width * height {
+            return (0..width * height).collect();
+        }
+        previous
+            .iter()
+            .enumerate()
+            .filter(|&(index, &previous)| {
+                (self.display[index / width] & pixel_bit(index % width) != 0) != previous
+            })
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Return the pixels of the first plane of the display, row by row.
+    #[deprecated(note = "the display is no longer a public field; use `framebuffer` instead")]
+    pub fn display(&self) -> Vec<bool> {
+        self.pixels(&self.display)
+    }
+
+    /// Return the first plane of the display packed eight pixels per byte, row by row, with the
+    /// leftmost pixel in the most significant bit, like a sprite.
+    ///
+    /// The display is packed at the low resolution of `WIDTH` by `HEIGHT`, so each row is
+    /// `WIDTH / 8` bytes. In high resolution, each packed pixel covers a block of 2x2 pixels, and
+    /// is set if any of them is.
+    pub fn display_bytes(&self) -> [u8; WIDTH * HEIGHT / 8] {
+        let mut bytes = [0; WIDTH * HEIGHT / 8];
+        for (y, packed) in bytes.chunks_mut(WIDTH / 8).enumerate() {
+            let row = if self.hires {
+                let rows = self.display[2 * y] | self.display[2 * y + 1];
+                (0..WIDTH)
+                    .filter(|&x| rows & (pixel_bit(2 * x) | pixel_bit(2 * x + 1)) != 0)
+                    .fold(0, |row, x| row | pixel_bit(x))
+            } else {
+                self.display[y]
+            };
+            packed.copy_from_slice(&row.to_be_bytes()[..WIDTH / 8]);
+        }
+        bytes
+    }
+
+    /// Return the colour of the pixel at (`x`, `y`) as a number from 0 to 3, combining the
+    /// bit-planes: bit 0 is set by `display` and bit 1 by the second plane.
+    ///
+    /// Outside XO-CHIP mode, the second plane is always clear, so this is 0 or 1.
+    ///
+    /// # Panics
+    /// Panics if (`x`, `y`) is outside the display.
+    pub fn pixel_colour(&self, x: usize, y: usize) -> u8 {
+        assert!(x < self.width() && y < self.height());
+        let bit = pixel_bit(x);
+        (self.display[y] & bit != 0) as u8 | ((self.second_plane[y] & bit != 0) as u8) << 1
+    }
+
+    /// Return whether the pixel at (`x`, `y`) is set in the first plane of the display, or `false`
+    /// if it is outside the display in the active resolution.
+    pub fn get_pixel(&self, x: usize, y: usize) -> bool {
+        x < self.width() && y < self.height() && self.display[y] & pixel_bit(x) != 0
+    }
+
+    /// Set or clear the pixel at (`x`, `y`) in the first plane of the display, as if a program had
+    /// drawn it. Pixels outside the display in the active resolution are ignored.
+    pub fn set_pixel(&mut self, x: usize, y: usize, on: bool) {
+        if x >= self.width() || y >= self.height() {
+            return;
+        }
+        if on {
+            self.display[y] |= pixel_bit(x);
+        } else {
+            self.display[y] &= !pixel_bit(x);
+        }
+        self.mark_dirty(x, y);
+        self.display_changed();
+    }
+
+    /// Clear both planes of the display, as if a program had cleared it.
+    ///
+    /// Unlike `00E0`, this ignores the `selected_planes`.
+    pub fn clear_display(&mut self) {
+        self.display.fill(0);
+        self.second_plane.fill(0);
+        self.mark_all_dirty();
+        self.display_changed();
+    }
+
+    /// Return whether the XO-CHIP extensions are enabled.
+    pub fn is_xochip(&self) -> bool {
+        self.xochip
+    }
+
+    /// Enable or disable the XO-CHIP extensions.
+    ///
+    /// This resizes `memory` to `XOCHIP_MEMORY_SIZE` or `MEMORY_SIZE` respectively, keeping its
+    /// contents up to the new size.
+    pub fn set_xochip(&mut self, xochip: bool) {
+        self.xochip = xochip;
+        let size = if xochip { XOCHIP_MEMORY_SIZE } else { MEMORY_SIZE };
+        self.memory.resize(size, 0);
+    }
+
+    /// Detect the platform the loaded program was written for, and switch to the quirks and the
+    /// instruction set of its preset, returning the preset.
+    ///
+    /// The ROM last loaded with `load_file` is scanned for the instructions SUPER-CHIP and XO-CHIP
+    /// add, choosing `ProcessorConfig::xochip` if it uses any XO-CHIP instruction, and
+    /// `ProcessorConfig::schip` if it uses any other SUPER-CHIP instruction. If it uses neither,
+    /// the quirks and the instruction set are left as they are and `None` is returned. As with
+    /// `validate`, the scan is linear, so data that looks like these instructions counts too.
+    /// `Dxy0` is not taken as a sign of SUPER-CHIP, as it is valid in CHIP-8, where it draws
+    /// nothing, and only draws a large sprite after the `00FF` that is.
+    pub fn auto_configure(&mut self) -> Option<ProcessorConfig> {
+        let end = (0x200 + self.rom_size).min(self.memory.len());
+        let mut config = None;
+        for (_, _, instruction) in decode_rom(&self.memory[0x200..end]) {
+            match instruction {
+                Some(Instruction::Scu(_))
+                | Some(Instruction::LdILong)
+                | Some(Instruction::Plane(_))
+                | Some(Instruction::Audio) => {
+                    config = Some(ProcessorConfig::xochip());
+                    break;
                 }
-                // 0nnn - SYS addr
-                // Jump to a machine code routine at nnn.
-                // This instruction is only used on the old computers on which Chip-8 was originally
-                // implemented. It is ignored by modern interpreters.
+                Some(Instruction::Scd(_))
+                | Some(Instruction::Scr)
+                | Some(Instruction::Scl)
+                | Some(Instruction::Exit)
+                | Some(Instruction::Low)
+                | Some(Instruction::High)
+                | Some(Instruction::LdHf { .. })
+                | Some(Instruction::LdRVx { .. })
+                | Some(Instruction::LdVxR { .. }) => config = Some(ProcessorConfig::schip()),
                 _ => {}
-            },
-            // 1nnn - JP addr
-            // Jump to location nnn.
-            // The interpreter sets the program counter to nnn.
-            0x1 => self.program_counter = nnn,
-            // 2nnn - CALL addr
-            // Call subroutine at nnn.
-            // The interpreter increments the stack pointer, then puts the current PC on the top of
-            // the stack. The PC is then set to nnn.
-            0x2 => {
-                self.stack[self.stack_pointer] = self.program_counter as u16;
-                self.stack_pointer += 1;
-                self.program_counter = nnn;
             }
-            // 3xkk - SE Vx, byte
-            // Skip next instruction if Vx = kk.
-            // The interpreter compares register Vx to kk, and if they are equal, increments the program counter by 2.
-            0x3 => if V![x] == kk {
-                self.program_counter += 2
-            },
-            // 4xkk - SNE Vx, byte
-            // Skip next instruction if Vx != kk.
-            // The interpreter compares register Vx to kk, and if they are not equal, increments the
-            // program counter by 2.
-            0x4 => if V![x] != kk {
-                self.program_counter += 2;
-            },
-            // 5xy0 - SE Vx, Vy
-            // Skip next instruction if Vx = Vy.
-            // The interpreter compares register Vx to register Vy, and if they are equal,
-            // increments the program counter by 2.
-            0x5 => if V![x] == V![y] {
-                self.program_counter += 2;
-            },
-            // 6xkk - LD Vx, byte
-            // Set Vx = kk.
-            // The interpreter puts the value kk into register Vx.
-            0x6 => V![x] = kk,
-            // 7xkk - ADD Vx, byte
-            // Set Vx = Vx + kk.
-            // Adds the value kk to the value of register Vx, then stores the result in Vx.
-            0x7 => V![x] = V![x].wrapping_add(kk),
+        }
+        if let Some(config) = config {
+            self.quirks = config.quirks;
+            self.set_xochip(config.xochip);
+        }
+        config
+    }
+
+    /// Return the XO-CHIP audio pattern loaded with `F002`, for a host to play while the sound
+    /// timer is nonzero.
+    pub fn audio_buffer(&self) -> &[u8; 16] {
+        &self.audio_buffer
+    }
+
+    /// Return the number of instructions executed since the last reset.
+    ///
+    /// Steps that execute nothing, e.g. while stopped at a breakpoint, are not counted. This is
+    /// the time used by the timeline of `record_input`.
+    pub fn cycles(&self) -> u64 {
+        self.cycle_count
+    }
+
+    /// Return whether the program has exited with `00FD`.
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Return whether the program is spinning in place, i.e. the last instruction executed was a
+    /// `1nnn` jumping to its own address.
+    ///
+    /// Many programs do this to stop once they are done, so the host can sleep or execute fewer
+    /// instructions until the keys or the timers change. The program still executes normally.
+    pub fn is_idle(&self) -> bool {
+        self.idle
+    }
+
+    /// Return whether the processor is waiting for the timers to tick after a `Dxyn`, under the
+    /// `display_wait` quirk.
+    ///
+    /// A host that ticks the timers at 60 Hz does not need to check this, as `step` does
+    /// nothing while waiting, but it can use it to stop executing instructions until the next
+    /// frame.
+    pub fn is_waiting_for_vblank(&self) -> bool {
+        self.waiting_for_vblank
+    }
+
+    /// Return whether the display has changed since it was last presented.
+    ///
+    /// A new processor needs to be presented once, so that the initial blank display is shown.
+    pub fn needs_present(&self) -> bool {
+        self.draw
+    }
+
+    /// Request a present, regardless of the `present_mode`.
+    pub fn present(&mut self) {
+        self.draw = true;
+        self.pending_present = false;
+    }
+
+    /// Mark the display as presented.
+    pub fn mark_presented(&mut self) {
+        self.draw = false;
+        self.dirty_region = None;
+    }
+
+    /// Return whether the display needs to be presented, and mark it as presented like
+    /// `mark_presented`.
+    ///
+    /// Read the `dirty_region` before calling this, as it is cleared as well.
+    pub fn take_draw(&mut self) -> bool {
+        let draw = self.draw;
+        self.mark_presented();
+        draw
+    }
+
+    /// Return the region of the display changed since it was last presented, if any.
+    pub fn dirty_region(&self) -> Option<Region> {
+        self.dirty_region
+    }
+
+    /// Write `value` to memory at `address`.
+    pub fn poke(&mut self, address: usize, value: u8) -> Result<(), Error> {
+        match self.memory.get_mut(address) {
+            Some(byte) => {
+                *byte = value;
+                Ok(())
+            }
+            None => Err(Error::MemoryOutOfBounds { addr: address }),
+        }
+    }
+
+    /// Take the memory accesses logged since the last call, leaving the log empty.
+    pub fn take_memory_log(&mut self) -> Vec<MemoryAccess> {
+        mem::take(&mut self.memory_log)
+    }
+
+    /// Return a hex dump of `len` bytes of memory from `start`, clamped to the end of memory.
+    ///
+    /// Each line holds 16 bytes: the address, the bytes in hexadecimal and the bytes as ASCII
+    /// between `|`s, with `.` for unprintable bytes.
+    pub fn hexdump(&self, start: usize, len: usize) -> String {
+        let start = start.min(self.memory.len());
+        let end = start.saturating_add(len).min(self.memory.len());
+        let mut dump = String::new();
+        for (line, bytes) in self.memory[start..end].chunks(16).enumerate() {
+            dump.push_str(&format!("{:04X} ", start + line * 16));
+            for column in 0..16 {
+                match bytes.get(column) {
+                    Some(byte) => dump.push_str(&format!(" {:02X}", byte)),
+                    None => dump.push_str("   "),
+                }
+            }
+            dump.push_str("  |");
+            for &byte in bytes {
+                dump.push(if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                });
+            }
+            dump.push_str("|\n");
+        }
+        dump
+    }
+
+    /// Return the instructions counted since the last reset while `profile_opcodes` was set.
+    pub fn opcode_stats(&self) -> &OpcodeStats {
+        &self.opcode_stats
+    }
+
+    /// Get the current `opcode`.
+    ///
+    /// Bytes past the end of memory read as 0; `step` returns an error instead of executing them.
+    pub fn opcode(&self) -> u16 {
+        let byte = |address: usize| self.memory.get(address).map_or(0, |&byte| byte as u16);
+        byte(self.program_counter) << 8 | byte(self.program_counter + 1)
+    }
+
+    /// Emulate a processor cycle, executing a single instruction with `step` and then ticking the
+    /// timers once with `tick_timers`.
+    ///
+    /// This is only suitable for hosts executing one instruction per 60 Hz frame; see the timing
+    /// section of the `Processor` documentation.
+    ///
+    /// If `step` stops at a breakpoint, the timers are not ticked. The event of the instruction
+    /// is returned, or a `CycleEvent::BeepStop` if there is none and ticking the timers stopped
+    /// the buzzer.
+    pub fn run_cycle(&mut self) -> Result<CycleEvent, Error> {
+        let event = self.step()?;
+        if let CycleEvent::Breakpoint(_) = event {
+            return Ok(event);
+        }
+        let beeping = self.beeping;
+        self.tick_timers();
+        Ok(match event {
+            CycleEvent::None => self.cycle_event(beeping),
+            event => event,
+        })
+    }
+
+    /// Emulate a 60 Hz frame, executing `cycles` instructions with `step` and then ticking the
+    /// timers once with `tick_timers`.
+    ///
+    /// Use `Processor::hz_to_cycles_per_frame` to find the number of instructions per frame for a
+    /// clock speed. If an instruction fails or `step` stops at a breakpoint, the remaining
+    /// instructions are not executed and the timers are not ticked.
+    ///
+    /// Return the events of the frame other than `CycleEvent::None`, each once, in the order they
+    /// first happened. Unlike with `step`, an event is not hidden by another in the same
+    /// instruction, so e.g. both `CycleEvent::Draw` and `CycleEvent::BeepStart` are reported for
+    /// a frame that draws and starts the buzzer. A `CycleEvent::Breakpoint` is always the last
+    /// event.
+    pub fn run_frame(&mut self, cycles: usize) -> Result<Vec<CycleEvent>, Error> {
+        let mut events = Vec::new();
+        let mut add_event = |event| {
+            if event != CycleEvent::None && !events.contains(&event) {
+                events.push(event);
+            }
+        };
+        for _ in 0..cycles {
+            let beeping = self.beeping;
+            let event = self.step()?;
+            if let CycleEvent::Breakpoint(_) = event {
+                add_event(event);
+                return Ok(events);
+            }
+            // `step` only reports the first of the events of an instruction.
+            if self.halted {
+                add_event(CycleEvent::Halted);
+            }
+            if self.beeping != beeping {
+                add_event(self.beep_event());
+            }
+            if self.changed_display {
+                add_event(CycleEvent::Draw);
+            }
+        }
+        let beeping = self.beeping;
+        self.tick_timers();
+        if self.beeping != beeping {
+            add_event(self.beep_event());
+        }
+        if self.halted {
+            add_event(CycleEvent::Halted);
+        }
+        Ok(events)
+    }
+
+    /// Execute `cycles` instructions without a host, ticking the timers once every 12
+    /// instructions as if running at roughly 700 instructions per second.
+    ///
+    /// This is deterministic when the processor is created with a fixed `rng_seed`, so it is
+    /// suitable for tests. Breakpoints are not stopped at. The first instruction that fails stops
+    /// execution and its error is returned.
+    pub fn run_for(&mut self, cycles: usize) -> Result<(), Error> {
+        for cycle in 1..=cycles {
+            self.step()?;
+            if cycle % HEADLESS_CYCLES_PER_FRAME == 0 {
+                self.tick_timers();
+            }
+        }
+        Ok(())
+    }
+
+    /// Execute instructions like `run_for` until the program counter is `pc`, and return whether
+    /// it got there within `max_cycles` instructions.
+    ///
+    /// This returns `Ok(true)` straight away if the program counter is already `pc`, and
+    /// `Ok(false)` once the program has exited. The first instruction that fails stops execution
+    /// and its error is returned.
+    pub fn run_until(&mut self, pc: usize, max_cycles: usize) -> Result<bool, Error> {
+        for cycle in 1..=max_cycles {
+            if self.program_counter == pc {
+                return Ok(true);
+            }
+            if self.step()? == CycleEvent::Halted {
+                return Ok(false);
+            }
+            if cycle % HEADLESS_CYCLES_PER_FRAME == 0 {
+                self.tick_timers();
+            }
+        }
+        Ok(self.program_counter == pc)
+    }
+
+    /// Execute a single instruction.
+    ///
+    /// This does not tick the timers; see `tick_timers`. This does nothing once the program has
+    /// exited, or under the `display_wait` quirk while the processor waits for the timers to tick
+    /// after a `Dxyn`.
+    ///
+    /// Return what happened as a `CycleEvent`. If the program counter is at a breakpoint, this
+    /// returns `CycleEvent::Breakpoint` instead of executing the instruction. The next call
+    /// executes it, so that the host can resume. Once the program has exited, this returns
+    /// `CycleEvent::Halted`.
+    ///
+    /// If the program counter has run past the end of memory, this returns an
+    /// `Error::MemoryOutOfBounds` instead of panicking.
+    ///
+    /// The `draw` flag and the beep callback work as before, so hosts can use either them or the
+    /// events.
+    pub fn step(&mut self) -> Result<CycleEvent, Error> {
+        // V![$index] is the register at $index.
+        macro_rules! V {
+            [ $index:expr ] => { self.registers[$index] };
+        }
+
+        while let Some(&(cycle, mask)) = self.playback.keys.get(self.playback_position) {
+            if cycle as u64 > self.cycle_count {
+                break;
+            }
+            self.set_keys(mask);
+            self.playback_position += 1;
+        }
+
+        self.changed_display = false;
+
+        if self.halted {
+            return Ok(CycleEvent::Halted);
+        }
+        if self.waiting_for_vblank {
+            return Ok(CycleEvent::None);
+        }
+
+        if self.stopped_at_breakpoint.take() != Some(self.program_counter)
+            && self.breakpoints.contains(&self.program_counter)
+        {
+            self.stopped_at_breakpoint = Some(self.program_counter);
+            return Ok(CycleEvent::Breakpoint(self.program_counter));
+        }
+
+        if self.quirks.strict_alignment && self.program_counter & 1 != 0 {
+            return Err(Error::MisalignedProgramCounter {
+                pc: self.program_counter,
+            });
+        }
+        self.check_program_counter()?;
+
+        if self.record_history && self.history_limit > 0 {
+            let history = mem::take(&mut self.history);
+            let snapshot = self.clone();
+            self.history = history;
+            if self.history.len() >= self.history_limit {
+                self.history.pop_front();
+            }
+            self.history.push_back(snapshot);
+        }
+
+        let beeping = self.beeping;
+        let opcode = self.opcode();
+
+        if let Some(ref mut callback) = self.trace_callback.0 {
+            callback(self.program_counter, opcode);
+        }
+
+        if self.profile_opcodes {
+            self.opcode_stats.classes[(opcode >> 12) as usize] += 1;
+        }
+        self.cycle_count += 1;
+        self.idle = false;
+
+        self.program_counter += 2;
+
+        let x: usize = (opcode as usize & 0x0F00) >> 8;
+        let y: usize = (opcode as usize & 0x00F0) >> 4;
+        let n: u8 = opcode as u8 & 0x000F;
+        let kk: u8 = opcode as u8;
+        let nnn: usize = opcode as usize & 0x0FFF;
+
+        match (opcode & 0xF000) >> 12 {
+            0x0 => match opcode & 0x0FFF {
+                // 00Cn - SCD nibble (SUPER-CHIP)
+                // Scroll the display down by n pixels.
+                // The top n rows are cleared.
+                0x0C0..=0x0CF => self.scroll(0, n as isize),
+                // 00Dn - SCU nibble (XO-CHIP)
+                // Scroll the display up by n pixels.
+                // The bottom n rows are cleared.
+                0x0D0..=0x0DF if self.xochip => self.scroll(0, -(n as isize)),
+                // 00E0 - CLS
+                // Clear the display.
+                // In XO-CHIP mode, only the selected planes are cleared.
+                0x0E0 => {
+                    let planes = self.active_planes();
+                    for plane in 0..2 {
+                        if planes & (1 << plane) != 0 {
+                            self.plane_mut(plane).fill(0);
+                        }
+                    }
+                    self.mark_all_dirty();
+                    self.display_changed();
+                }
+                // 00EE - RET
+                // Return from a subroutine.
+                // The interpreter sets the program counter to the address at the top of the stack,
+                // then subtracts 1 from the stack pointer.
+                0x0EE => {
+                    if self.stack_pointer == 0 {
+                        return Err(Error::StackUnderflow {
+                            pc: self.program_counter - 2,
+                        });
+                    }
+                    self.stack_pointer -= 1;
+                    self.program_counter = self.stack[self.stack_pointer] as usize;
+                }
+                // 00FB - SCR (SUPER-CHIP)
+                // Scroll the display right by 4 pixels.
+                // The 4 leftmost columns are cleared.
+                0x0FB => self.scroll(4, 0),
+                // 00FC - SCL (SUPER-CHIP)
+                // Scroll the display left by 4 pixels.
+                // The 4 rightmost columns are cleared.
+                0x0FC => self.scroll(-4, 0),
+                // 00FD - EXIT (SUPER-CHIP)
+                // Exit the interpreter.
+                // The processor halts; further cycles do nothing.
+                0x0FD => self.halted = true,
+                // 00FE - LOW (SUPER-CHIP)
+                // Switch to the low-resolution (64x32) mode.
+                // The display is cleared.
+                0x0FE => self.set_resolution(false),
+                // 00FF - HIGH (SUPER-CHIP)
+                // Switch to the high-resolution (128x64) mode.
+                // The display is cleared.
+                0x0FF => self.set_resolution(true),
+                // 0nnn - SYS addr
+                // Jump to a machine code routine at nnn.
+                // This instruction is only used on the old computers on which Chip-8 was originally
+                // implemented. It is ignored by modern interpreters.
+                _ => {}
+            },
+            // 1nnn - JP addr
+            // Jump to location nnn.
+            // The interpreter sets the program counter to nnn.
+            // A jump to itself makes the processor idle, see `is_idle`.
+            0x1 => {
+                self.idle = nnn == self.program_counter - 2;
+                self.program_counter = nnn;
+            }
+            // 2nnn - CALL addr
+            // Call subroutine at nnn.
+            // The interpreter increments the stack pointer, then puts the current PC on the top of
+            // the stack. The PC is then set to nnn.
+            // The program counter has already been advanced past this instruction, so the address
+            // put on the stack is that of the instruction following the CALL, which is where 00EE
+            // resumes execution.
+            0x2 => {
+                if self.stack_pointer >= self.stack.len() {
+                    return Err(Error::StackOverflow {
+                        pc: self.program_counter - 2,
+                    });
+                }
+                self.stack[self.stack_pointer] = self.program_counter as u16;
+                self.stack_pointer += 1;
+                self.program_counter = nnn;
+            }
+            // 3xkk - SE Vx, byte
+            // Skip next instruction if Vx = kk.
+            // The interpreter compares register Vx to kk, and if they are equal, increments the program counter by 2.
+            0x3 => if V![x] == kk {
+                self.skip_next_instruction();
+            },
+            // 4xkk - SNE Vx, byte
+            // Skip next instruction if Vx != kk.
+            // The interpreter compares register Vx to kk, and if they are not equal, increments the
+            // program counter by 2.
+            0x4 => if V![x] != kk {
+                self.skip_next_instruction();
+            },
+            // 5xy0 - SE Vx, Vy
+            // Skip next instruction if Vx = Vy.
+            // The interpreter compares register Vx to register Vy, and if they are equal,
+            // increments the program counter by 2.
+            0x5 => {
+                self.check_encoding(opcode, 0x000F)?;
+                if V![x] == V![y] {
+                    self.skip_next_instruction();
+                }
+            }
+            // 6xkk - LD Vx, byte
+            // Set Vx = kk.
+            // The interpreter puts the value kk into register Vx.
+            0x6 => V![x] = kk,
+            // 7xkk - ADD Vx, byte
+            // Set Vx = Vx + kk.
+            // Adds the value kk to the value of register Vx, then stores the result in Vx.
+            0x7 => V![x] = V![x].wrapping_add(kk),
+            // The opcodes setting VF write it last, so that it holds the flag when x is 0xF.
             0x8 => match opcode & 0x000F {
                 // 8xy0 - LD Vx, Vy
                 // Set Vx = Vy.
@@ -230,20 +1660,36 @@ impl Processor {
                 // Performs a bitwise OR on the values of Vx and Vy, then stores the result in Vx.
                 // A bitwise OR compares the corresponding bits from two values, and if either bit
                 // is 1, then the same bit in the result is also 1. Otherwise, it is 0.
-                0x1 => V![x] |= V![y],
+                // Under the `logic_resets_vf` quirk, VF is then set to 0, as are 8xy2 and 8xy3.
+                0x1 => {
+                    V![x] |= V![y];
+                    if self.quirks.logic_resets_vf {
+                        V![0xF] = 0;
+                    }
+                }
                 // 8xy2 - AND Vx, Vy
                 // Set Vx = Vx AND Vy.
                 // Performs a bitwise AND on the values of Vx and Vy, then stores the result in Vx.
                 // A bitwise AND compares the corresponding bits from two values, and if both bits
                 // are 1, then the same bit in the result is also 1. Otherwise, it is 0.
-                0x2 => V![x] &= V![y],
+                0x2 => {
+                    V![x] &= V![y];
+                    if self.quirks.logic_resets_vf {
+                        V![0xF] = 0;
+                    }
+                }
                 // 8xy3 - XOR Vx, Vy
                 // Set Vx = Vx XOR Vy.
                 // Performs a bitwise exclusive OR on the values of Vx and Vy, then stores the
                 // result in Vx. An exclusive OR compares the corresponding bits from two values,
                 // and if the bits are not both the same, then the corresponding bit in the result
                 // is set to 1. Otherwise, it is 0.
-                0x3 => V![x] ^= V![y],
+                0x3 => {
+                    V![x] ^= V![y];
+                    if self.quirks.logic_resets_vf {
+                        V![0xF] = 0;
+                    }
+                }
                 // 8xy4 - ADD Vx, Vy
                 // Set Vx = Vx + Vy, set VF = carry.
                 // The values of Vx and Vy are added together. If the result is greater than 8 bits
@@ -251,8 +1697,8 @@ impl Processor {
                 // are kept, and stored in Vx.
                 0x4 => {
                     let (value, carry) = V![x].overflowing_add(V![y]);
-                    V![0xF] = if carry { 1 } else { 0 };
                     V![x] = value;
+                    V![0xF] = if carry { 1 } else { 0 };
                 }
                 // 8xy5 - SUB Vx, Vy
                 // Set Vx = Vx - Vy, set VF = NOT borrow.
@@ -260,16 +1706,18 @@ impl Processor {
                 // the results stored in Vx.
                 0x5 => {
                     let (value, borrow) = V![x].overflowing_sub(V![y]);
-                    V![0xF] = if borrow { 0 } else { 1 };
                     V![x] = value;
+                    V![0xF] = if borrow { 0 } else { 1 };
                 }
                 // 8xy6 - SHR Vx {, Vy}
                 // Set Vx = Vx SHR 1.
                 // If the least-significant bit of Vx is 1, then VF is set to 1, otherwise 0. Then
                 // Vx is divided by 2.
+                // Under the `shift_uses_vy` quirk, Vy is shifted instead of Vx.
                 0x6 => {
-                    V![0xF] = V![x] & 0x1;
-                    V![x] >>= 1;
+                    let value = if self.quirks.shift_uses_vy { V![y] } else { V![x] };
+                    V![x] = value >> 1;
+                    V![0xF] = value & 0x1;
                 }
                 // 8xy7 - SUBN Vx, Vy
                 // Set Vx = Vy - Vx, set VF = NOT borrow.
@@ -277,31 +1725,31 @@ impl Processor {
                 // the results stored in Vx.
                 0x7 => {
                     let (value, borrow) = V![y].overflowing_sub(V![x]);
-                    V![0xF] = if borrow { 0 } else { 1 };
                     V![x] = value;
+                    V![0xF] = if borrow { 0 } else { 1 };
                 }
                 // 8xyE - SHL Vx {, Vy}
                 // Set Vx = Vx SHL 1.
                 // If the most-significant bit of Vx is 1, then VF is set to 1, otherwise to 0. Then
                 // Vx is multiplied by 2.
+                // Under the `shift_uses_vy` quirk, Vy is shifted instead of Vx.
                 0xE => {
-                    V![0xF] = if V![x] & 0x80 == 1 << 7 { 1 } else { 0 };
-                    V![x] <<= 1;
-                }
-                _ => {
-                    return Err(format!(
-                        "Unknown opcode at 0x{:X}: 0x{:04X}.",
-                        self.program_counter, opcode
-                    ).into())
+                    let value = if self.quirks.shift_uses_vy { V![y] } else { V![x] };
+                    V![x] = value << 1;
+                    V![0xF] = (value >> 7) & 0x1;
                 }
+                _ => return self.skip_unknown_opcode(opcode),
             },
             // 9xy0 - SNE Vx, Vy
             // Skip next instruction if Vx != Vy.
             // The values of Vx and Vy are compared, and if they are not equal, the program counter
             // is increased by 2.
-            0x9 => if V![x] != V![y] {
-                self.program_counter += 2;
-            },
+            0x9 => {
+                self.check_encoding(opcode, 0x000F)?;
+                if V![x] != V![y] {
+                    self.skip_next_instruction();
+                }
+            }
             // Annn - LD I, addr
             // Set I = nnn.
             // The value of register I is set to nnn.
@@ -309,13 +1757,18 @@ impl Processor {
             // Bnnn - JP V0, addr
             // Jump to location nnn + V0.
             // The program counter is set to nnn plus the value of V0.
-            0xB => self.program_counter = V![0] as usize + nnn,
+            // Under the `jump_uses_vx` quirk, this is BXNN - JP Vx, addr, which jumps to XNN plus
+            // the value of Vx.
+            0xB => {
+                let offset = if self.quirks.jump_uses_vx { V![x] } else { V![0] };
+                self.program_counter = offset as usize + nnn;
+            }
             // Cxkk - RND Vx, byte
             // Set Vx = random byte AND kk.
             // The interpreter generates a random number from 0 to 255, which is then ANDed with the
             // value kk. The results are stored in Vx. See instruction 8xy2 for more information on
             // AND.
-            0xC => V![x] = self.rng.gen::<u8>() & kk,
+            0xC => V![x] = self.random_byte() & kk,
             // Dxyn - DRW Vx, Vy, nibble
             // Display n-byte sprite starting at memory location I at (Vx, Vy), set
             // VF = collision.
@@ -326,23 +1779,85 @@ impl Processor {
             // coordinates of the display, it wraps around to the opposite side of the screen. See
             // instruction 8xy3 for more information on XOR, and section 2.4, Display, for more
             // information on the Chip-8 screen and sprites.
+            // Under the `sprite_clipping` quirk, the parts of the sprite outside the display are
+            // clipped instead of wrapped; the coordinates (Vx, Vy) themselves still wrap.
+            // Under the `display_wait` quirk, the processor then waits for the timers to tick.
+            // Dxy0 - DRW Vx, Vy, 0 (SUPER-CHIP)
+            // Display a 16x16 sprite of 32 bytes starting at memory location I at (Vx, Vy), set
+            // VF = collision. Each row is two bytes, the left half first.
+            // This is only the case in high resolution or in XO-CHIP mode. In the low resolution
+            // of CHIP-8, it is a sprite of zero bytes, which draws nothing and sets VF to 0.
+            // In XO-CHIP mode, a sprite is drawn into each selected plane. When both planes are
+            // selected, the sprite for the second plane directly follows the one for the first.
+            // When no plane is selected, nothing is drawn and VF is left unchanged.
+            0xD if self.active_planes() == 0 => {}
             0xD => {
-                self.draw = true;
-                V![0xF] = 0;
-                for col in 0..n as usize {
-                    let pixel = self.memory[self.index + col];
-                    for row in 0..8 {
-                        if pixel & (0x80 >> row) != 0 {
-                            let x_coord = (V![x] as usize + row) % WIDTH;
-                            let y_coord = (V![y] as usize + col) % HEIGHT;
-                            let index = x_coord + y_coord * WIDTH;
-
-                            if self.display[index] {
-                                V![0xF] = 1;
+                self.display_changed();
+                let (width, height) = (self.width(), self.height());
+                let x_start = V![x] as usize % width;
+                let y_start = V![y] as usize % height;
+                let (bytes_per_row, rows) = match n {
+                    0 if self.hires || self.xochip => (2, 16),
+                    n => (1, n as usize),
+                };
+                let sprite_width = bytes_per_row * 8;
+                let sprite_len = bytes_per_row * rows;
+                let planes = self.active_planes();
+                let draw_mode = self.draw_mode;
+                let row_mask = self.row_mask();
+                let mut collision = false;
+                self.log_memory(
+                    MemoryAccessKind::Read,
+                    self.index..self.index + sprite_len * planes.count_ones() as usize,
+                    opcode,
+                );
+                let mut sprite_start = self.index;
+                for plane in 0..2 {
+                    if planes & (1 << plane) == 0 {
+                        continue;
+                    }
+                    for row in 0..rows {
+                        let y_coord = y_start + row;
+                        if self.quirks.sprite_clipping && y_coord >= height {
+                            break;
+                        }
+                        let y_coord = y_coord % height;
+                        // Reads past the end of memory wrap around to the start.
+                        let bits = (0..bytes_per_row).fold(0, |bits, byte| {
+                            let address = (sprite_start + row * bytes_per_row + byte)
+                                % self.memory.len();
+                            bits << 8 | self.memory[address] as u128
+                        });
+                        // Move the leftmost pixel of the sprite to the most significant bit, then
+                        // to column x_start. Columns past the right edge are wrapped around to
+                        // the left edge, unless they are clipped.
+                        let bits = bits << (128 - sprite_width);
+                        let mut sprite_row = bits >> x_start & row_mask;
+                        if !self.quirks.sprite_clipping && x_start + sprite_width > width {
+                            sprite_row |= bits << (width - x_start) & row_mask;
+                        }
+                        if sprite_row == 0 {
+                            continue;
+                        }
+                        self.mark_dirty(sprite_row.leading_zeros() as usize, y_coord);
+                        self.mark_dirty(127 - sprite_row.trailing_zeros() as usize, y_coord);
+
+                        let pixels = &mut self.plane_mut(plane)[y_coord];
+                        match draw_mode {
+                            DrawMode::Xor => {
+                                if *pixels & sprite_row != 0 {
+                                    collision = true;
+                                }
+                                *pixels ^= sprite_row;
                             }
-                            self.display[index] ^= true;
+                            DrawMode::Or => *pixels |= sprite_row,
                         }
                     }
+                    sprite_start += sprite_len;
+                }
+                V![0xF] = if collision { 1 } else { 0 };
+                if self.quirks.display_wait {
+                    self.waiting_for_vblank = true;
                 }
             }
             0xE => match opcode & 0x00FF {
@@ -350,24 +1865,42 @@ impl Processor {
                 // Skip next instruction if key with the value of Vx is pressed.
                 // Checks the keyboard, and if the key corresponding to the value of Vx is currently
                 // in the down position, PC is increased by 2.
-                0x9E => if self.keypad[V![x] as usize] {
-                    self.program_counter += 2;
+                // Only the low nibble of Vx selects the key, as on the COSMAC VIP.
+                0x9E => if self.keypad[V![x] as usize & 0xF] {
+                    self.skip_next_instruction();
                 },
                 // ExA1 - SKNP Vx
                 // Skip next instruction if key with the value of Vx is not pressed.
                 // Checks the keyboard, and if the key corresponding to the value of Vx is currently
                 // in the up position, PC is increased by 2.
-                0xA1 => if !self.keypad[V![x] as usize] {
-                    self.program_counter += 2;
+                0xA1 => if !self.keypad[V![x] as usize & 0xF] {
+                    self.skip_next_instruction();
                 },
-                _ => {
-                    return Err(format!(
-                        "Unknown opcode at 0x{:X}: 0x{:04X}.",
-                        self.program_counter, opcode
-                    ).into())
-                }
+                _ => return self.skip_unknown_opcode(opcode),
             },
             0xF => match opcode & 0x00FF {
+                // F000 NNNN - LD I, NNNN (XO-CHIP)
+                // Set I = NNNN, the 16-bit address in the word following this instruction.
+                // This instruction is four bytes long, so PC is increased by another 2.
+                // If the address is past the end of memory, this is an `Error::MemoryOutOfBounds`.
+                0x00 if self.xochip && x == 0 => {
+                    self.check_program_counter()?;
+                    self.index = self.opcode() as usize;
+                    self.program_counter += 2;
+                }
+                // Fn01 - PLANE n (XO-CHIP)
+                // Select the bit-planes n (0-3) that subsequent drawing, clearing and scrolling
+                // affect. With no planes selected, these instructions leave the display unchanged.
+                0x01 if self.xochip => self.selected_planes = x as u8,
+                // F002 - AUDIO (XO-CHIP)
+                // Load the 16-byte audio pattern starting at memory location I into the audio
+                // buffer, played while the sound timer is nonzero.
+                0x02 if self.xochip && x == 0 => {
+                    self.log_memory(MemoryAccessKind::Read, self.index..self.index + 16, opcode);
+                    for (i, byte) in self.audio_buffer.iter_mut().enumerate() {
+                        *byte = self.memory[(self.index + i) % self.memory.len()];
+                    }
+                }
                 // Fx07 - LD Vx, DT
                 // Set Vx = delay timer value.
                 // The value of DT is placed into Vx.
@@ -376,6 +1909,19 @@ impl Processor {
                 // Wait for a key press, store the value of the key in Vx
                 // All execution stops until a key is pressed, then the value of that key is stored
                 // in Vx.
+                // Under the `wait_for_key_release` quirk, the first key pressed is stored once it
+                // is released.
+                0x0A if self.quirks.wait_for_key_release => match self.awaited_key {
+                    Some(key) if !self.keypad[key] => {
+                        V![x] = key as u8;
+                        self.awaited_key = None;
+                    }
+                    Some(_) => self.program_counter -= 2,
+                    None => {
+                        self.awaited_key = self.keypad.iter().position(|&pressed| pressed);
+                        self.program_counter -= 2;
+                    }
+                },
                 0x0A => {
                     let mut key_press = false;
                     for (i, key) in self.keypad.iter().enumerate() {
@@ -401,77 +1947,2059 @@ impl Processor {
                 // Fx1E - ADD I, Vx
                 // Set I = I + Vx.
                 // The values of I and Vx are added, and the results are stored in I.
-                0x1E => self.index += V![x] as usize,
+                // Under the `i_overflow_sets_vf` quirk, VF is set to 1 if the result is greater
+                // than 0xFFF, otherwise 0.
+                0x1E => {
+                    let index = self.index + V![x] as usize;
+                    if self.quirks.i_overflow_sets_vf {
+                        V![0xF] = if index > 0xFFF { 1 } else { 0 };
+                    }
+                    self.index = index;
+                }
                 // Fx29 - LD F, Vx
                 // Set I = location of sprite for digit Vx.
                 // The value of I is set to the location for the hexadecimal sprite corresponding to
                 // the value of Vx. See section 2.4, Display, for more information on the Chip-8
                 // hexadecimal font.
-                0x29 => self.index = 5 * V![x] as usize,
+                // The font is at address 0 unless another one was set with `set_fontset`.
+                0x29 => self.index = self.font_address + 5 * V![x] as usize,
+                // Fx30 - LD HF, Vx (SUPER-CHIP)
+                // Set I = location of the high-resolution sprite for digit Vx.
+                // The sprites are 8x10 pixels, see `FONTSET_HIGH`.
+                0x30 => self.index = FONTSET_HIGH_ADDRESS + 10 * V![x] as usize,
                 // Fx33 - LD B, Vx
                 // Store BCD representation of Vx in memory locations I, I+1, and I+2.
                 // The interpreter takes the decimal value of Vx, and places the hundreds digit in
                 // memory at location in I, the tens digit at location I+1, and the ones digit at
                 // location I+2.
+                // Locations past the end of memory are an `Error::MemoryOutOfBounds`.
                 0x33 => {
-                    self.memory[self.index] = V![x] / 100;
-                    self.memory[self.index + 1] = (V![x] / 10) % 10;
-                    self.memory[self.index + 2] = V![x] % 10;
+                    let range = self.index_range(3)?;
+                    self.check_write(self.index)?;
+                    self.memory[range.clone()]
+                        .copy_from_slice(&[V![x] / 100, (V![x] / 10) % 10, V![x] % 10]);
+                    self.log_memory(MemoryAccessKind::Write, range, opcode);
                 }
                 // Fx55 - LD [I], Vx
                 // Store registers V0 through Vx in memory starting at location I. The interpreter
                 // copies the values of registers V0 through Vx into memory, starting at the address
                 // in I.
-                0x55 => self.memory[self.index..self.index + x + 1]
-                    .copy_from_slice(&self.registers[0x0..x + 1]),
+                // I is then incremented according to the `index_increment` quirk.
+                // Locations past the end of memory are an `Error::MemoryOutOfBounds`.
+                0x55 => {
+                    let range = self.index_range(x + 1)?;
+                    self.check_write(self.index)?;
+                    self.memory[range.clone()].copy_from_slice(&self.registers[0x0..x + 1]);
+                    self.log_memory(MemoryAccessKind::Write, range, opcode);
+                    self.increment_index(x);
+                }
                 // Fx65 - LD Vx, [I]
                 // Read registers V0 through Vx from memory starting at location I. The interpreter
                 // reads values from memory starting at location I into registers V0 through Vx.
-                0x65 => self.registers[0x0..x + 1]
-                    .copy_from_slice(&self.memory[self.index..self.index + x + 1]),
-                _ => {
-                    return Err(format!(
-                        "Unknown opcode at 0x{:X}: 0x{:04X}.",
-                        self.program_counter, opcode
-                    ).into())
+                // I is then incremented according to the `index_increment` quirk.
+                // Locations past the end of memory are an `Error::MemoryOutOfBounds`.
+                0x65 => {
+                    let range = self.index_range(x + 1)?;
+                    self.log_memory(MemoryAccessKind::Read, range.clone(), opcode);
+                    self.registers[0x0..x + 1].copy_from_slice(&self.memory[range]);
+                    self.increment_index(x);
+                }
+                // Fx75 - LD R, Vx (SUPER-CHIP)
+                // Store registers V0 through Vx in the RPL user flags, x <= 7.
+                0x75 if x < self.rpl_flags.len() => {
+                    self.rpl_flags[..x + 1].copy_from_slice(&self.registers[..x + 1]);
+                }
+                // Fx85 - LD Vx, R (SUPER-CHIP)
+                // Read registers V0 through Vx from the RPL user flags, x <= 7.
+                0x85 if x < self.rpl_flags.len() => {
+                    self.registers[..x + 1].copy_from_slice(&self.rpl_flags[..x + 1]);
                 }
+                _ => return self.skip_unknown_opcode(opcode),
             },
-            _ => {
-                return Err(format!(
-                    "Unknown opcode at 0x{:X}: 0x{:04X}.",
-                    self.program_counter, opcode
-                ).into())
-            }
+            _ => return self.skip_unknown_opcode(opcode),
+        }
+
+        self.update_beep();
+        Ok(self.cycle_event(beeping))
+    }
+
+    /// Return a snapshot of the state of the emulated machine.
+    pub fn state(&self) -> ProcessorState {
+        ProcessorState {
+            memory: self.memory.clone(),
+            registers: self.registers,
+            index: self.index,
+            program_counter: self.program_counter,
+            display: self.pixels(&self.display),
+            second_plane: self.pixels(&self.second_plane),
+            xochip: self.xochip,
+            selected_planes: self.selected_planes,
+            hires: self.hires,
+            halted: self.halted,
+            waiting_for_vblank: self.waiting_for_vblank,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            audio_buffer: self.audio_buffer,
+            stack: self.stack,
+            stack_pointer: self.stack_pointer,
+            rpl_flags: self.rpl_flags,
+            keypad: self.keypad,
+            pending_keypad: self.pending_keypad,
+            awaited_key: self.awaited_key,
+            cycle_count: self.cycle_count,
         }
+    }
+
+    /// Undo the last instruction executed by `step` while `record_history` was set, together with
+    /// any timer ticks and key changes since.
+    ///
+    /// The callbacks and the history settings are kept. Return `false` if there is no history
+    /// left to undo.
+    pub fn step_back(&mut self) -> bool {
+        let snapshot = match self.history.pop_back() {
+            Some(snapshot) => snapshot,
+            None => return false,
+        };
+        let beeping = self.beeping;
+        *self = Processor {
+            record_history: self.record_history,
+            history_limit: self.history_limit,
+            history: mem::take(&mut self.history),
+            beeping,
+            beep_callback: mem::take(&mut self.beep_callback),
+            trace_callback: mem::take(&mut self.trace_callback),
+            ..snapshot
+        };
+        self.update_beep();
+        true
+    }
+
+    /// Stop execution with `CycleEvent::Breakpoint` when the program counter reaches `address`.
+    pub fn add_breakpoint(&mut self, address: usize) {
+        self.breakpoints.insert(address);
+    }
+
+    /// Remove the breakpoint at `address`, if any.
+    pub fn remove_breakpoint(&mut self, address: usize) {
+        self.breakpoints.remove(&address);
+    }
 
-        if self.delay_timer > 0 {
-            self.delay_timer -= 1;
+    /// Decrement the delay and sound timers.
+    ///
+    /// The timers count down at 60 Hz, so this should be called 60 times per second, independently
+    /// of how many instructions are executed with `step`. This is also when polled keys become
+    /// visible, when a `PerFrame` present is requested, and when a processor waiting for the
+    /// vertical blank under the `display_wait` quirk resumes.
+    pub fn tick_timers(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+        self.waiting_for_vblank = false;
+        if self.poll_keypad {
+            self.keypad = self.pending_keypad;
         }
-        if self.sound_timer > 0 {
-            self.sound_timer -= 1;
+        if self.present_mode == PresentMode::PerFrame && self.pending_present {
+            self.draw = true;
+            self.pending_present = false;
         }
+        self.update_beep();
+    }
 
-        Ok(())
+    /// Return whether the host should sound the buzzer, which is while the sound timer is nonzero.
+    pub fn should_beep(&self) -> bool {
+        self.sound_timer > 0
     }
-}
 
-impl Default for Processor {
-    fn default() -> Processor {
-        let mut memory = [0; 4096];
-        memory[..80].copy_from_slice(&FONTSET);
-        Processor {
-            memory,
-            registers: [0; 16],
-            index: 0,
-            program_counter: 0x200,
-            display: [false; WIDTH * HEIGHT],
-            draw: true,
-            delay_timer: 0,
-            sound_timer: 0,
-            stack: [0; 16],
-            stack_pointer: 0,
-            keypad: [false; 16],
-            rng: SmallRng::from_entropy(),
+    /// Fill `out` with mono samples at `sample_rate` for the buzzer: a square wave of
+    /// `beep_frequency` while `should_beep` returns `true`, and silence otherwise.
+    ///
+    /// The phase of the wave carries over between calls, so consecutive buffers join without
+    /// clicks.
+    pub fn fill_audio(&self, out: &mut [f32], sample_rate: u32) {
+        const AMPLITUDE: f32 = 0.25;
+
+        if !self.should_beep() {
+            for sample in out {
+                *sample = 0.0;
+            }
+            return;
         }
+        let step = self.beep_frequency / sample_rate as f32;
+        let mut phase = self.audio_phase.get();
+        for sample in out {
+            *sample = if phase < 0.5 { AMPLITUDE } else { -AMPLITUDE };
+            phase += step;
+            // Keep the fractional part, without `f32::fract`, which needs `std`.
+            phase -= phase as u32 as f32;
+        }
+        self.audio_phase.set(phase);
+    }
+
+    /// Set a function called with `true` when the buzzer starts sounding and with `false` when it
+    /// stops, replacing the previous one.
+    ///
+    /// The changes are detected at the end of `step` and `tick_timers`. The callback is not
+    /// shared with clones of the processor.
+    pub fn set_beep_callback<F: FnMut(bool) + 'static>(&mut self, callback: F) {
+        self.beep_callback = Callback(Some(Box::new(callback)));
+    }
+
+    /// Set a function called with the program counter and the opcode before each instruction is
+    /// executed, replacing the previous one.
+    ///
+    /// The callback is not shared with clones of the processor.
+    pub fn set_trace_callback<F: FnMut(usize, u16) + 'static>(&mut self, callback: F) {
+        self.trace_callback = Callback(Some(Box::new(callback)));
+    }
+
+    /// Call the beep callback if `should_beep` changed since it was last called.
+    fn update_beep(&mut self) {
+        let beeping = self.should_beep();
+        if beeping != self.beeping {
+            self.beeping = beeping;
+            if let Some(ref mut callback) = self.beep_callback.0 {
+                callback(beeping);
+            }
+        }
+    }
+
+    /// Increment the index after `Fx55` or `Fx65` copied registers V0 through V`x`, according to
+    /// the `index_increment` quirk.
+    fn increment_index(&mut self, x: usize) {
+        match self.quirks.index_increment {
+            IndexIncrement::None => {}
+            IndexIncrement::ByX => self.index += x,
+            IndexIncrement::ByXPlus1 => self.index += x + 1,
+        }
+    }
+
+    /// Scroll the display `dx` pixels to the right and `dy` pixels down, clearing the pixels
+    /// scrolled in at the edges.
+    ///
+    /// In XO-CHIP mode, only the selected planes are scrolled.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let height = self.height() as isize;
+        let planes = self.active_planes();
+        let row_mask = self.row_mask();
+        for plane in 0..2 {
+            if planes & (1 << plane) == 0 {
+                continue;
+            }
+            let pixels = self.plane_mut(plane);
+            let previous = pixels.to_vec();
+            for (y, row) in pixels.iter_mut().enumerate() {
+                let from_y = y as isize - dy;
+                *row = if (0..height).contains(&from_y) {
+                    let from = previous[from_y as usize];
+                    if dx >= 0 {
+                        from >> dx & row_mask
+                    } else {
+                        from << -dx & row_mask
+                    }
+                } else {
+                    0
+                };
+            }
+        }
+        self.mark_all_dirty();
+        self.display_changed();
+    }
+
+    /// Skip the next instruction, for the conditional skip instructions.
+    ///
+    /// In XO-CHIP mode, `F000 NNNN` is skipped as a whole, so PC is increased by 4 instead of 2.
+    fn skip_next_instruction(&mut self) {
+        if self.xochip && self.opcode() == 0xF000 {
+            self.program_counter += 4;
+        } else {
+            self.program_counter += 2;
+        }
+    }
+
+    /// Return the planes affected by drawing, clearing and scrolling, as a bit mask: bit 0 for
+    /// `display` and bit 1 for the second plane. Outside XO-CHIP mode, this is only `display`.
+    fn active_planes(&self) -> u8 {
+        if self.xochip {
+            self.selected_planes & 0b11
+        } else {
+            0b01
+        }
+    }
+
+    /// Return the rows of `plane`, 0 for `display` and 1 for the second plane.
+    fn plane_mut(&mut self, plane: usize) -> &mut [u128] {
+        if plane == 0 {
+            &mut self.display
+        } else {
+            &mut self.second_plane
+        }
+    }
+
+    /// Return the pixels of `plane`, one of `display` and `second_plane`, row by row.
+    fn pixels(&self, plane: &[u128]) -> Vec<bool> {
+        let width = self.width();
+        plane
+            .iter()
+            .flat_map(|&row| (0..width).map(move |x| row & pixel_bit(x) != 0))
+            .collect()
+    }
+
+    /// Return the bits of a row of the display that are inside the display in the active
+    /// resolution.
+    fn row_mask(&self) -> u128 {
+        !0 << (128 - self.width())
+    }
+
+    /// Add the whole display to the dirty region.
+    fn mark_all_dirty(&mut self) {
+        self.dirty_region = Some(Region {
+            x: 0,
+            y: 0,
+            width: self.width(),
+            height: self.height(),
+        });
+    }
+
+    /// Switch to the high-resolution mode if `hires` is `true`, or the low-resolution mode
+    /// otherwise, and clear the display.
+    ///
+    /// The display buffer is resized together with the resolution, so that it always has a row
+    /// for each row of the display.
+    fn set_resolution(&mut self, hires: bool) {
+        self.hires = hires;
+        self.display = vec![0; self.height()];
+        self.second_plane = vec![0; self.height()];
+        self.mark_all_dirty();
+        self.display_changed();
+    }
+
+    /// Add the pixel at (`x`, `y`) to the dirty region.
+    fn mark_dirty(&mut self, x: usize, y: usize) {
+        self.dirty_region = Some(match self.dirty_region {
+            Some(region) => region.including(x, y),
+            None => Region {
+                x,
+                y,
+                width: 1,
+                height: 1,
+            },
+        });
+    }
+
+    /// Return the event to report after executing an instruction, if the buzzer was sounding
+    /// before it according to `was_beeping`.
+    fn cycle_event(&self, was_beeping: bool) -> CycleEvent {
+        if self.halted {
+            CycleEvent::Halted
+        } else if self.beeping != was_beeping {
+            self.beep_event()
+        } else if self.changed_display {
+            CycleEvent::Draw
+        } else {
+            CycleEvent::None
+        }
+    }
+
+    /// Return the event for the buzzer changing to its current state.
+    fn beep_event(&self) -> CycleEvent {
+        if self.beeping {
+            CycleEvent::BeepStart
+        } else {
+            CycleEvent::BeepStop
+        }
+    }
+
+    /// Record that the display changed, requesting a present according to the `present_mode`.
+    fn display_changed(&mut self) {
+        self.changed_display = true;
+        match self.present_mode {
+            PresentMode::Immediate => self.draw = true,
+            PresentMode::PerFrame | PresentMode::Manual => self.pending_present = true,
+        }
+    }
+
+    /// Log an access of `kind` by `opcode` to each address in `addresses`, if logging is enabled.
+    ///
+    /// Addresses past the end of memory wrap around to the start.
+    fn log_memory(&mut self, kind: MemoryAccessKind, addresses: Range<usize>, opcode: u16) {
+        if !self.log_memory_access {
+            return;
+        }
+        for address in addresses {
+            let address = address % self.memory.len();
+            let value = self.memory[address];
+            self.memory_log.push(MemoryAccess {
+                kind,
+                address,
+                value,
+                opcode,
+            });
+        }
+    }
+
+    /// Return an `Error::UnknownOpcode` for `opcode`, or skip it under the `skip_unknown_opcodes`
+    /// quirk.
+    fn skip_unknown_opcode(&self, opcode: u16) -> Result<CycleEvent, Error> {
+        if self.quirks.skip_unknown_opcodes {
+            Ok(CycleEvent::None)
+        } else {
+            Err(self.unknown_opcode(opcode))
+        }
+    }
+
+    /// Create the error for an unknown `opcode`.
+    fn unknown_opcode(&self, opcode: u16) -> Error {
+        Error::UnknownOpcode {
+            pc: self.program_counter - 2,
+            opcode,
+        }
+    }
+
+    /// Check that the bits of `opcode` selected by `mask`, which are fixed by its encoding, are
+    /// zero.
+    ///
+    /// Malformed opcodes are only rejected under the `strict_encoding` quirk; otherwise the bits
+    /// are ignored.
+    fn check_encoding(&self, opcode: u16, mask: u16) -> Result<(), Error> {
+        if self.quirks.strict_encoding && opcode & mask != 0 {
+            return Err(self.unknown_opcode(opcode));
+        }
+        Ok(())
+    }
+
+    /// Set the state of a key, without recording it.
+    fn write_key(&mut self, key: usize, pressed: bool) {
+        self.pending_keypad[key] = pressed;
+        if !self.poll_keypad {
+            self.keypad[key] = pressed;
+        }
+    }
+
+    /// Record the keys in `input_log` if recording and they changed since the last record.
+    fn record_keys(&mut self) {
+        if !self.recording_input {
+            return;
+        }
+        let mask = self
+            .pending_keypad
+            .iter()
+            .enumerate()
+            .fold(0, |mask, (key, &pressed)| mask | (pressed as u16) << key);
+        if self.input_log.keys.last().map(|&(_, last)| last) != Some(mask) {
+            self.input_log.keys.push((self.cycle_count as usize, mask));
+        }
+    }
+
+    /// Return the next random byte for `Cxkk`, replaying and recording it as requested.
+    fn random_byte(&mut self) -> u8 {
+        let byte = match self.playback.random_bytes.get(self.random_position) {
+            Some(&byte) => {
+                self.random_position += 1;
+                byte
+            }
+            None => self.rng.gen(),
+        };
+        if self.recording_input {
+            self.input_log.random_bytes.push(byte);
+        }
+        byte
+    }
+
+    /// Return the addresses of the `len` bytes of memory starting at the index, or an error if they
+    /// extend past the end of memory.
+    fn index_range(&self, len: usize) -> Result<Range<usize>, Error> {
+        let end = self.index + len;
+        if end > self.memory.len() {
+            return Err(Error::MemoryOutOfBounds {
+                addr: self.index.max(self.memory.len()),
+            });
+        }
+        Ok(self.index..end)
+    }
+
+    /// Check that the word at the program counter is inside memory.
+    fn check_program_counter(&self) -> Result<(), Error> {
+        if self.program_counter + 1 >= self.memory.len() {
+            return Err(Error::MemoryOutOfBounds {
+                addr: self.program_counter.max(self.memory.len()),
+            });
+        }
+        Ok(())
+    }
+
+    /// Check whether an instruction may write to memory starting at `address`.
+    fn check_write(&self, address: usize) -> Result<(), Error> {
+        if self.quirks.protect_interpreter_region && address < 0x200 {
+            return Err(Error::ProtectedMemoryWrite { addr: address });
+        }
+        Ok(())
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for Processor {
+    type Error = Error;
+
+    /// Create a new `Processor` and load `file` into memory, returning an error if `file` does not
+    /// fit in memory.
+    fn try_from(file: &'a [u8]) -> Result<Processor, Error> {
+        let mut processor = Processor::default();
+        processor.load_file(file)?;
+        Ok(processor)
+    }
+}
+
+/// Return a random number generator seeded from the operating system.
+#[cfg(feature = "std")]
+fn default_rng() -> SmallRng {
+    SmallRng::from_entropy()
+}
+
+/// Return a random number generator with a fixed seed, as there is no source of entropy without
+/// `std`. Use `Processor::with_seed` to seed it.
+#[cfg(not(feature = "std"))]
+fn default_rng() -> SmallRng {
+    SmallRng::from_seed([0x5A; 16])
+}
+
+/// Return a random number generator seeded with `seed`.
+fn seeded_rng(seed: u64) -> SmallRng {
+        // Expand the seed to the 16 bytes the RNG needs with SplitMix64, as similar seeds give
+        // poorly distributed numbers when used directly.
+        let mut state = seed;
+        let mut rng_seed = [0; 16];
+        for chunk in rng_seed.chunks_mut(8) {
+            state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^= z >> 31;
+            for (i, byte) in chunk.iter_mut().enumerate() {
+                *byte = (z >> (8 * i)) as u8;
+            }
+        }
+    SmallRng::from_seed(rng_seed)
+}
+
+/// Return the bit of column `x` in a row of the display.
+fn pixel_bit(x: usize) -> u128 {
+    1 << (127 - x)
+}
+
+impl Default for Processor {
+    fn default() -> Processor {
+        let mut memory = vec![0; MEMORY_SIZE];
+        memory[..80].copy_from_slice(&FONTSET);
+        memory[FONTSET_HIGH_ADDRESS..FONTSET_HIGH_ADDRESS + 160].copy_from_slice(&FONTSET_HIGH);
+        Processor {
+            memory,
+            registers: [0; 16],
+            index: 0,
+            program_counter: 0x200,
+            display: vec![0; HEIGHT],
+            second_plane: vec![0; HEIGHT],
+            xochip: false,
+            font: FONTSET.to_vec(),
+            font_address: 0,
+            rom_size: 0,
+            selected_planes: 0b01,
+            hires: false,
+            draw: true,
+            present_mode: PresentMode::default(),
+            pending_present: false,
+            dirty_region: None,
+            waiting_for_vblank: false,
+            halted: false,
+            idle: false,
+            draw_mode: DrawMode::default(),
+            delay_timer: 0,
+            sound_timer: 0,
+            beep_frequency: 440.0,
+            audio_phase: Cell::new(0.0),
+            audio_buffer: [0; 16],
+            stack: [0; 16],
+            stack_pointer: 0,
+            rpl_flags: [0; 8],
+            keypad: [false; 16],
+            poll_keypad: false,
+            pending_keypad: [false; 16],
+            quirks: Quirks::default(),
+            log_memory_access: false,
+            memory_log: Vec::new(),
+            profile_opcodes: false,
+            opcode_stats: OpcodeStats::default(),
+            awaited_key: None,
+            cycle_count: 0,
+            recording_input: false,
+            input_log: InputLog::default(),
+            playback: InputLog::default(),
+            playback_position: 0,
+            random_position: 0,
+            rng: default_rng(),
+            breakpoints: BTreeSet::new(),
+            stopped_at_breakpoint: None,
+            record_history: false,
+            history_limit: 1000,
+            history: VecDeque::new(),
+            beeping: false,
+            changed_display: false,
+            beep_callback: Callback(None),
+            trace_callback: Callback(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Return a processor with a fixed seed and `rom` loaded.
+    fn processor(rom: &[u8]) -> Processor {
+        let mut processor = Processor::with_seed(0);
+        processor.load_file(rom).unwrap();
+        processor
+    }
+
+    /// Execute `steps` instructions of `processor`, returning the result of the last one.
+    fn run(processor: &mut Processor, steps: usize) -> Result<CycleEvent, Error> {
+        let mut event = Ok(CycleEvent::None);
+        for _ in 0..steps {
+            event = Ok(processor.step()?);
+        }
+        event
+    }
+
+    /// Set every pixel of the display of `processor`.
+    fn fill_display(processor: &mut Processor) {
+        for y in 0..processor.height() {
+            for x in 0..processor.width() {
+                processor.set_pixel(x, y, true);
+            }
+        }
+    }
+
+    #[test]
+    fn hz_to_cycles_per_frame_rounds_to_nearest() {
+        assert_eq!(Processor::hz_to_cycles_per_frame(0), 0);
+        assert_eq!(Processor::hz_to_cycles_per_frame(29), 0);
+        assert_eq!(Processor::hz_to_cycles_per_frame(30), 1);
+        assert_eq!(Processor::hz_to_cycles_per_frame(500), 8);
+        assert_eq!(Processor::hz_to_cycles_per_frame(540), 9);
+        assert_eq!(Processor::hz_to_cycles_per_frame(700), 12);
+        assert_eq!(Processor::hz_to_cycles_per_frame(u32::MAX), 71_582_788);
+    }
+
+    #[test]
+    fn custom_fontset_is_used_by_fx29() {
+        let font: Vec<u8> = (0..80).collect();
+        let mut processor = processor(&[0x60, 0x0A, 0xF0, 0x29]);
+        processor.set_fontset(&font, 0x50).unwrap();
+        run(&mut processor, 2).unwrap();
+        assert_eq!(processor.index, 0x50 + 5 * 0xA);
+        assert_eq!(processor.memory[processor.index], 50);
+
+        match processor.set_fontset(&font, 0x1C0) {
+            Err(Error::MemoryOutOfBounds { addr: 0x20F }) => {}
+            result => panic!("{:?}", result),
+        }
+        match processor.set_fontset(&font, usize::MAX - 3) {
+            Err(Error::MemoryOutOfBounds { .. }) => {}
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn auto_configure_detects_schip() {
+        // LOW; SCR 4; JP 0x200
+        let mut schip = processor(&[0x00, 0xFE, 0x00, 0xC4, 0x12, 0x00]);
+        assert_eq!(schip.auto_configure(), Some(ProcessorConfig::schip()));
+        assert_eq!(schip.quirks, ProcessorConfig::schip().quirks);
+        assert!(!schip.is_xochip());
+    }
+
+    #[test]
+    fn auto_configure_keeps_quirks_of_plain_rom() {
+        // LD V0, 0x05; ADD V0, 0x01; JP 0x202
+        let mut chip8 = processor(&[0x60, 0x05, 0x70, 0x01, 0x12, 0x02]);
+        chip8.quirks.jump_uses_vx = true;
+        let quirks = chip8.quirks;
+        assert_eq!(chip8.auto_configure(), None);
+        assert_eq!(chip8.quirks, quirks);
+        assert!(!chip8.is_xochip());
+    }
+
+    #[test]
+    fn auto_configure_only_scans_loaded_rom() {
+        // LD V0, 0x05, followed by HIGH outside the ROM
+        let mut chip8 = processor(&[0x60, 0x05]);
+        chip8.memory[0x202..0x204].copy_from_slice(&[0x00, 0xFF]);
+        assert_eq!(chip8.auto_configure(), None);
+        assert_eq!(chip8.quirks, Quirks::default());
+
+        // A ROM replaced by a shorter one leaves its bytes behind.
+        let mut reloaded = processor(&[0x60, 0x05, 0x00, 0xFF]);
+        reloaded.load_file(&[0x60, 0x05]).unwrap();
+        assert_eq!(reloaded.auto_configure(), None);
+    }
+
+    #[test]
+    fn auto_configure_detects_xochip() {
+        // LOW; PLANE 3
+        let mut xochip = processor(&[0x00, 0xFE, 0xF3, 0x01]);
+        assert_eq!(xochip.auto_configure(), Some(ProcessorConfig::xochip()));
+        assert!(xochip.is_xochip());
+    }
+
+    #[test]
+    fn recorded_random_bytes_replay_exactly() {
+        // RND V0, 0xFF; RND V1, 0x0F; RND V2, 0xF0; JP 0x200
+        let rom = [0xC0, 0xFF, 0xC1, 0x0F, 0xC2, 0xF0, 0x12, 0x00];
+        let mut recorded = processor(&rom);
+        recorded.record_input();
+        run(&mut recorded, 40).unwrap();
+        let log = recorded.input_log().clone();
+        assert_eq!(log.random_bytes.len(), 30);
+
+        // The seed of the log alone reproduces the random bytes with the same RNG algorithm.
+        let mut reseeded = processor(&rom);
+        reseeded.play_input(&InputLog {
+            random_bytes: Vec::new(),
+            ..log.clone()
+        });
+        run(&mut reseeded, 40).unwrap();
+        assert_eq!(reseeded.registers, recorded.registers);
+
+        // An RNG seeded differently after starting the replay stands in for a change to the RNG
+        // algorithm, which the recorded random bytes make up for.
+        let mut replayed = processor(&rom);
+        replayed.play_input(&log);
+        replayed.rng = seeded_rng(!log.seed);
+        run(&mut replayed, 40).unwrap();
+        assert_eq!(replayed.registers, recorded.registers);
+    }
+
+    #[test]
+    fn protection_covers_font_area() {
+        // LD I, 0x050; LD [I], V1
+        let mut processor = Processor::with_seed(0);
+        processor.load_file(&[0xA0, 0x50, 0xF1, 0x55]).unwrap();
+        processor.quirks.protect_interpreter_region = true;
+        processor.registers[..2].copy_from_slice(&[0xAA, 0xBB]);
+        processor.step().unwrap();
+        match processor.step() {
+            Err(Error::ProtectedMemoryWrite { addr: 0x50 }) => {}
+            result => panic!("{:?}", result),
+        }
+        assert_eq!(&processor.memory[0x50..0x52], &FONTSET_HIGH[..2]);
+    }
+
+    #[test]
+    fn packed_display_matches_unpacked_reference() {
+        // A linear congruential generator, to draw many sprites reproducibly.
+        let mut state = 12345u32;
+        let mut next = move |bound: u32| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) % bound
+        };
+        for &(hires, clipping) in &[(false, false), (false, true), (true, false), (true, true)] {
+            let mut processor = Processor::with_seed(0);
+            processor.load_file(&[0x00, if hires { 0xFF } else { 0xFE }]).unwrap();
+            processor.quirks.sprite_clipping = clipping;
+            processor.step().unwrap();
+            let (width, height) = (processor.width(), processor.height());
+            // The display as the unpacked `Vec<bool>` it used to be, drawn to pixel by pixel.
+            let mut reference = vec![false; width * height];
+            for _ in 0..500 {
+                let (x, y, n) = (next(256) as u8, next(256) as u8, next(16) as u8);
+                for address in 0x300..0x320 {
+                    processor.memory[address] = next(256) as u8;
+                }
+                // DRW V0, V1, n
+                processor.memory[0x200] = 0xD0;
+                processor.memory[0x201] = 0x10 | n;
+                processor.program_counter = 0x200;
+                processor.index = 0x300;
+                processor.registers[0] = x;
+                processor.registers[1] = y;
+                processor.step().unwrap();
+
+                let (sprite_width, rows) = match n {
+                    0 if hires => (16, 16),
+                    n => (8, n as usize),
+                };
+                let mut collision = false;
+                for row in 0..rows {
+                    for column in 0..sprite_width {
+                        let byte = processor.memory[0x300 + row * sprite_width / 8 + column / 8];
+                        if byte & (0x80 >> (column % 8)) == 0 {
+                            continue;
+                        }
+                        let (px, py) = (x as usize % width + column, y as usize % height + row);
+                        if clipping && (px >= width || py >= height) {
+                            continue;
+                        }
+                        let pixel = &mut reference[px % width + py % height * width];
+                        collision |= *pixel;
+                        *pixel = !*pixel;
+                    }
+                }
+                assert_eq!(processor.framebuffer(), (width, height, reference.clone()));
+                assert_eq!(processor.registers[0xF], collision as u8);
+            }
+        }
+    }
+
+    #[test]
+    fn index_past_end_of_memory_is_an_error() {
+        for &opcode in &[0xF165, 0xF155, 0xF033] {
+            let mut processor = processor(&[0xAF, 0xFF, (opcode >> 8) as u8, opcode as u8]);
+            match run(&mut processor, 2) {
+                Err(Error::MemoryOutOfBounds { addr: 0x1000 }) => {}
+                result => panic!("{:04X}: {:?}", opcode, result),
+            }
+        }
+    }
+
+    #[test]
+    fn long_index_past_end_of_memory_is_an_error() {
+        let mut processor = processor(&[0xF0, 0x00, 0xFF, 0xFF, 0xF1, 0x55]);
+        processor.set_xochip(true);
+        match run(&mut processor, 2) {
+            Err(Error::MemoryOutOfBounds { addr: 0x10000 }) => {}
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn program_counter_past_end_of_memory_is_an_error() {
+        // Zeroed memory is SYS 000, which is ignored, so the PC runs to the end of memory.
+        let mut zeroes = processor(&[0x60, 0x01]);
+        match zeroes.run_for(2000) {
+            Err(Error::MemoryOutOfBounds { addr: 0x1000 }) => {}
+            result => panic!("{:?}", result),
+        }
+        assert_eq!(zeroes.program_counter, 0x1000);
+
+        let mut jump = processor(&[0x60, 0xFF, 0xBF, 0xFF]);
+        match jump.run_until(0x300, 10) {
+            Err(Error::MemoryOutOfBounds { addr: 0x10FE }) => {}
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn key_opcodes_use_low_nibble_of_vx() {
+        let mut processor = processor(&[0x60, 0x23, 0xE0, 0x9E]);
+        processor.set_key(3, true);
+        run(&mut processor, 2).unwrap();
+        assert_eq!(processor.program_counter, 0x206);
+    }
+
+    #[test]
+    fn strict_alignment_rejects_odd_program_counter() {
+        // JP 0x201; LD V0, 0x01
+        let rom = [0x12, 0x01, 0x60, 0x01];
+        let mut strict = processor(&rom);
+        strict.quirks.strict_alignment = true;
+        match run(&mut strict, 2) {
+            Err(Error::MisalignedProgramCounter { pc: 0x201 }) => {}
+            result => panic!("{:?}", result),
+        }
+
+        // By default, the opcode at 0x201 (0x0160, SYS 0x160) is executed and ignored.
+        let mut lenient = processor(&rom);
+        run(&mut lenient, 2).unwrap();
+        assert_eq!(lenient.program_counter, 0x203);
+    }
+
+    #[test]
+    fn high_resolution_resizes_and_clears_display() {
+        // HIGH
+        let mut processor = processor(&[0x00, 0xFF]);
+        processor.set_pixel(3, 4, true);
+        run(&mut processor, 1).unwrap();
+        let (width, height, pixels) = processor.framebuffer();
+        assert_eq!((width, height), (HIRES_WIDTH, HIRES_HEIGHT));
+        assert_eq!(pixels.len(), 8192);
+        assert!(pixels.iter().all(|&pixel| !pixel));
+    }
+
+    #[test]
+    fn try_from_rejects_oversized_rom() {
+        let valid = Processor::try_from(&[0x60, 0x01][..]).unwrap();
+        assert_eq!(&valid.memory[0x200..0x202], &[0x60, 0x01]);
+
+        match Processor::try_from(&[0; MEMORY_SIZE - 0x1FF][..]) {
+            Err(Error::RomTooLarge { size: 0xE01, max_size: 0xE00 }) => {}
+            result => panic!("{:?}", result.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn call_pushes_address_of_next_instruction() {
+        // CALL 0x206; LD V1, 0x02; JP 0x204; LD V0, 0x01; RET
+        let mut processor =
+            processor(&[0x22, 0x06, 0x61, 0x02, 0x12, 0x04, 0x60, 0x01, 0x00, 0xEE]);
+        run(&mut processor, 1).unwrap();
+        assert_eq!(processor.program_counter, 0x206);
+        assert_eq!(processor.stack_pointer, 1);
+        assert_eq!(processor.stack[0], 0x202);
+
+        run(&mut processor, 2).unwrap();
+        assert_eq!(processor.program_counter, 0x202);
+        assert_eq!(processor.stack_pointer, 0);
+        run(&mut processor, 1).unwrap();
+        assert_eq!(processor.registers[..2], [0x01, 0x02]);
+    }
+
+    #[test]
+    fn protected_interpreter_region_rejects_writes() {
+        // LD V0, 0xAB; LD I, 0x100; LD [I], V0
+        let rom = [0x60, 0xAB, 0xA1, 0x00, 0xF0, 0x55];
+        let mut protected = processor(&rom);
+        protected.quirks.protect_interpreter_region = true;
+        match run(&mut protected, 3) {
+            Err(Error::ProtectedMemoryWrite { addr: 0x100 }) => {}
+            result => panic!("{:?}", result),
+        }
+        assert_eq!(protected.memory[0x100], 0);
+
+        let mut unprotected = processor(&rom);
+        run(&mut unprotected, 3).unwrap();
+        assert_eq!(unprotected.memory[0x100], 0xAB);
+    }
+
+    #[test]
+    fn or_draw_mode_never_clears_pixels() {
+        // LD F, V0; DRW V0, V0, 5; DRW V0, V0, 5
+        let rom = [0xF0, 0x29, 0xD0, 0x05, 0xD0, 0x05];
+        let mut or = processor(&rom);
+        or.draw_mode = DrawMode::Or;
+        run(&mut or, 2).unwrap();
+        let drawn = or.framebuffer();
+        run(&mut or, 1).unwrap();
+        assert_eq!(or.framebuffer(), drawn);
+        assert!(or.get_pixel(0, 0));
+        assert_eq!(or.registers[0xF], 0);
+
+        let mut xor = processor(&rom);
+        run(&mut xor, 3).unwrap();
+        assert_eq!(xor.lit_pixels().count(), 0);
+        assert_eq!(xor.registers[0xF], 1);
+    }
+
+    #[test]
+    fn builtin_roms_load_and_run() {
+        for name in builtin_roms() {
+            let mut processor = Processor::with_seed(0);
+            processor.load_builtin(name).unwrap();
+            for _ in 0..10 {
+                processor.step().unwrap();
+                processor.tick_timers();
+            }
+        }
+        match Processor::new().load_builtin("missing") {
+            Err(Error::UnknownBuiltinRom { name }) => assert_eq!(name, "missing"),
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn strict_encoding_rejects_malformed_opcodes() {
+        for &strict in &[false, true] {
+            // 8x0F is not an instruction, so it is unknown regardless of the quirk.
+            let mut unknown = processor(&[0x81, 0x0F]);
+            unknown.quirks.strict_encoding = strict;
+            match run(&mut unknown, 1) {
+                Err(Error::UnknownOpcode { pc: 0x200, opcode: 0x810F }) => {}
+                result => panic!("{:?}", result),
+            }
+
+            // SE V0, V1 is well-formed as 5010, and malformed as 5011.
+            let mut well_formed = processor(&[0x50, 0x10]);
+            well_formed.quirks.strict_encoding = strict;
+            run(&mut well_formed, 1).unwrap();
+            assert_eq!(well_formed.program_counter, 0x204);
+
+            let mut malformed = processor(&[0x50, 0x11]);
+            malformed.quirks.strict_encoding = strict;
+            match run(&mut malformed, 1) {
+                Err(Error::UnknownOpcode { pc: 0x200, opcode: 0x5011 }) if strict => {}
+                Ok(_) if !strict => assert_eq!(malformed.program_counter, 0x204),
+                result => panic!("{:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn new_processor_needs_first_present() {
+        // LD V0, 0x01
+        let mut processor = processor(&[0x60, 0x01]);
+        assert!(processor.needs_present());
+        processor.mark_presented();
+        assert!(!processor.needs_present());
+        run(&mut processor, 1).unwrap();
+        assert!(!processor.needs_present());
+    }
+
+    #[test]
+    fn memory_log_records_fx65_reads() {
+        // LD I, 0x300; LD V2, [I]
+        let mut processor = processor(&[0xA3, 0x00, 0xF2, 0x65]);
+        processor.memory[0x300..0x303].copy_from_slice(&[7, 8, 9]);
+        processor.log_memory_access = true;
+        run(&mut processor, 2).unwrap();
+        let log = processor.take_memory_log();
+        let reads: Vec<_> = log
+            .iter()
+            .map(|access| (access.kind, access.address, access.value))
+            .collect();
+        assert_eq!(
+            reads,
+            vec![
+                (MemoryAccessKind::Read, 0x300, 7),
+                (MemoryAccessKind::Read, 0x301, 8),
+                (MemoryAccessKind::Read, 0x302, 9),
+            ]
+        );
+        assert!(log.iter().all(|access| access.opcode == 0xF265));
+        assert!(processor.take_memory_log().is_empty());
+    }
+
+    #[test]
+    fn presents_per_frame_depend_on_present_mode() {
+        // Three sprites, then an idle loop: DRW V0, V0, 5 (x3); JP 0x206
+        let rom = [0xD0, 0x05, 0xD0, 0x05, 0xD0, 0x05, 0x12, 0x06];
+        for &(mode, expected) in &[
+            (PresentMode::Immediate, 3),
+            (PresentMode::PerFrame, 1),
+            (PresentMode::Manual, 0),
+        ] {
+            let mut processor = processor(&rom);
+            processor.present_mode = mode;
+            processor.mark_presented();
+            let mut presents = 0;
+            for _ in 0..4 {
+                processor.step().unwrap();
+                presents += processor.take_draw() as usize;
+            }
+            processor.tick_timers();
+            presents += processor.take_draw() as usize;
+            assert_eq!(presents, expected, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn keypad_labels_round_trip() {
+        for index in 0..16 {
+            let label = keypad_label(index).unwrap();
+            assert_eq!(keypad_index(label), Some(index));
+            assert_eq!(keypad_index(label.to_ascii_lowercase()), Some(index));
+        }
+        assert_eq!(keypad_label(0xA), Some('A'));
+        assert_eq!(keypad_label(16), None);
+        assert_eq!(keypad_index('G'), None);
+    }
+
+    #[test]
+    fn polled_keypad_updates_when_timers_tick() {
+        // LD V0, 0x05; SKP V0; SKP V0
+        let mut processor = processor(&[0x60, 0x05, 0xE0, 0x9E, 0xE0, 0x9E]);
+        processor.poll_keypad = true;
+        run(&mut processor, 1).unwrap();
+        processor.set_key(5, true);
+        run(&mut processor, 1).unwrap();
+        assert_eq!(processor.program_counter, 0x204);
+
+        processor.tick_timers();
+        run(&mut processor, 1).unwrap();
+        assert_eq!(processor.program_counter, 0x208);
+    }
+
+    #[test]
+    fn two_plane_sprite_draws_into_both_planes() {
+        // PLANE 3; LD I, 0x208; DRW V0, V0, 2; JP 0x206, then the rows of the first plane followed
+        // by those of the second.
+        let rom = [0xF3, 0x01, 0xA2, 0x08, 0xD0, 0x02, 0x12, 0x06, 0xC0, 0x80, 0x80, 0x01];
+        let mut processor = processor(&rom);
+        processor.set_xochip(true);
+        run(&mut processor, 3).unwrap();
+        assert_eq!(processor.pixel_colour(0, 0), 3);
+        assert_eq!(processor.pixel_colour(1, 0), 1);
+        assert_eq!(processor.pixel_colour(0, 1), 1);
+        assert_eq!(processor.pixel_colour(7, 1), 2);
+        // `lit_pixels` only covers the first plane.
+        assert_eq!(processor.lit_pixels().count(), 3);
+        assert_eq!(processor.index, 0x208);
+    }
+
+    #[test]
+    fn shl_flag_is_msb_of_shifted_register() {
+        for &shift_uses_vy in &[false, true] {
+            // LD V0, 0x80; LD V1, 0x01; SHL V0, V1
+            let mut shifted = processor(&[0x60, 0x80, 0x61, 0x01, 0x80, 0x1E]);
+            shifted.quirks.shift_uses_vy = shift_uses_vy;
+            run(&mut shifted, 3).unwrap();
+            if shift_uses_vy {
+                assert_eq!((shifted.registers[0], shifted.registers[0xF]), (0x02, 0));
+            } else {
+                assert_eq!((shifted.registers[0], shifted.registers[0xF]), (0x00, 1));
+            }
+
+            // The flag overwrites the result when VF is the destination.
+            // LD VF, 0x01; LD V1, 0x80; SHL VF, V1
+            let mut flag = processor(&[0x6F, 0x01, 0x61, 0x80, 0x8F, 0x1E]);
+            flag.quirks.shift_uses_vy = shift_uses_vy;
+            run(&mut flag, 3).unwrap();
+            assert_eq!(flag.registers[0xF], shift_uses_vy as u8);
+        }
+    }
+
+    #[test]
+    fn flag_opcodes_overwrite_vf() {
+        // LD V0, a; LD V1, b; then the opcode, with the expected VF.
+        let cases: &[(u8, u8, u16, u8)] = &[
+            (0xFF, 0x01, 0x8014, 1),
+            (0x01, 0x01, 0x8014, 0),
+            (0x05, 0x03, 0x8015, 1),
+            (0x03, 0x05, 0x8015, 0),
+            (0x01, 0x00, 0x8016, 1),
+            (0x02, 0x00, 0x8016, 0),
+            (0x03, 0x05, 0x8017, 1),
+            (0x05, 0x03, 0x8017, 0),
+            (0x80, 0x00, 0x801E, 1),
+            (0x40, 0x00, 0x801E, 0),
+            (0x00, 0x00, 0xD015, 0),
+        ];
+        for &(a, b, opcode, expected) in cases {
+            for &initial in &[0x00, 0xAA] {
+                let mut processor =
+                    processor(&[0x60, a, 0x61, b, (opcode >> 8) as u8, opcode as u8]);
+                processor.registers[0xF] = initial;
+                run(&mut processor, 3).unwrap();
+                assert_eq!(processor.registers[0xF], expected, "{:04X} with {}, {}", opcode, a, b);
+            }
+        }
+
+        // DRW V0, V1, 5 twice collides.
+        let mut collision = processor(&[0xD0, 0x15, 0xD0, 0x15]);
+        collision.registers[0xF] = 0xAA;
+        run(&mut collision, 2).unwrap();
+        assert_eq!(collision.registers[0xF], 1);
+    }
+
+    #[test]
+    fn dirty_region_bounds_single_sprite() {
+        // LD V0, 0x0A; LD V1, 0x04; LD F, V0; DRW V0, V1, 5
+        let mut processor = processor(&[0x60, 0x0A, 0x61, 0x04, 0xF0, 0x29, 0xD0, 0x15]);
+        processor.mark_presented();
+        assert_eq!(processor.dirty_region(), None);
+        run(&mut processor, 4).unwrap();
+        // The sprite of A is four pixels wide.
+        assert_eq!(
+            processor.dirty_region(),
+            Some(Region {
+                x: 10,
+                y: 4,
+                width: 4,
+                height: 5,
+            })
+        );
+    }
+
+    #[test]
+    fn load_file_rejects_oversized_rom() {
+        let mut processor = Processor::new();
+        match processor.load_file(&[0xAB; 4000]) {
+            Err(Error::RomTooLarge { size: 4000, max_size: 0xE00 }) => {}
+            result => panic!("{:?}", result),
+        }
+        assert!(processor.memory[0x200..].iter().all(|&byte| byte == 0));
+        processor.load_file(&[0xAB; 0xE00]).unwrap();
+    }
+
+    #[test]
+    fn same_seed_gives_same_random_numbers() {
+        // RND V0, 0xFF; RND V1, 0xFF; RND V2, 0x0F
+        let rom = [0xC0, 0xFF, 0xC1, 0xFF, 0xC2, 0x0F];
+        let mut first = processor(&rom);
+        let mut second = processor(&rom);
+        run(&mut first, 3).unwrap();
+        run(&mut second, 3).unwrap();
+        assert_eq!(first.registers, second.registers);
+
+        let mut other = Processor::with_seed(1);
+        other.load_file(&rom).unwrap();
+        run(&mut other, 3).unwrap();
+        assert_ne!(other.registers, first.registers);
+    }
+
+    #[test]
+    fn shift_quirk_selects_source_register() {
+        // LD V0, 0x05; LD V1, 0x82; then the shift, with the expected V0 and VF in each mode.
+        let cases = [
+            (0x8016, false, 0x02, 1),
+            (0x8016, true, 0x41, 0),
+            (0x801E, false, 0x0A, 0),
+            (0x801E, true, 0x04, 1),
+        ];
+        for &(opcode, shift_uses_vy, result, flag) in &cases {
+            let mut shifted = processor(&[0x60, 0x05, 0x61, 0x82, 0x80, opcode as u8]);
+            shifted.quirks.shift_uses_vy = shift_uses_vy;
+            run(&mut shifted, 3).unwrap();
+            assert_eq!((shifted.registers[0], shifted.registers[0xF]), (result, flag));
+            assert_eq!(shifted.registers[1], 0x82);
+        }
+    }
+
+    #[test]
+    fn index_increment_quirk_changes_index() {
+        for &(increment, index) in &[
+            (IndexIncrement::None, 0x300),
+            (IndexIncrement::ByX, 0x302),
+            (IndexIncrement::ByXPlus1, 0x303),
+        ] {
+            // LD I, 0x300; LD [I], V2 and LD I, 0x300; LD V2, [I]
+            for &opcode in &[0x55, 0x65] {
+                let mut processor = processor(&[0xA3, 0x00, 0xF2, opcode]);
+                processor.quirks.index_increment = increment;
+                run(&mut processor, 2).unwrap();
+                assert_eq!(processor.index, index, "{:?}", increment);
+            }
+        }
+    }
+
+    #[test]
+    fn jump_quirk_selects_offset_register() {
+        // LD V0, 0x10; LD V3, 0x20; JP V0, 0x340
+        let rom = [0x60, 0x10, 0x63, 0x20, 0xB3, 0x40];
+        let mut v0 = processor(&rom);
+        run(&mut v0, 3).unwrap();
+        assert_eq!(v0.program_counter, 0x350);
+
+        let mut vx = processor(&rom);
+        vx.quirks.jump_uses_vx = true;
+        run(&mut vx, 3).unwrap();
+        assert_eq!(vx.program_counter, 0x360);
+    }
+
+    #[test]
+    fn index_overflow_quirk_sets_vf() {
+        for &quirk in &[false, true] {
+            // LD I, 0xFFE; LD V0, 0x01; ADD I, V0; LD VF, 0xAA; ADD I, V0
+            let mut processor =
+                processor(&[0xAF, 0xFE, 0x60, 0x01, 0xF0, 0x1E, 0x6F, 0xAA, 0xF0, 0x1E]);
+            processor.quirks.i_overflow_sets_vf = quirk;
+            run(&mut processor, 3).unwrap();
+            assert_eq!(processor.index, 0xFFF);
+            assert_eq!(processor.registers[0xF], 0);
+
+            run(&mut processor, 2).unwrap();
+            assert_eq!(processor.index, 0x1000);
+            assert_eq!(processor.registers[0xF], if quirk { 1 } else { 0xAA });
+        }
+    }
+
+    #[test]
+    fn sprite_clipping_quirk_at_right_edge() {
+        // LD V0, 0x3E; LD V1, 0x00; LD F, V1; DRW V0, V1, 1
+        let rom = [0x60, 0x3E, 0x61, 0x00, 0xF1, 0x29, 0xD0, 0x11];
+        let mut wrapped = processor(&rom);
+        run(&mut wrapped, 4).unwrap();
+        assert_eq!(wrapped.lit_pixels().collect::<Vec<_>>(), [(0, 0), (1, 0), (62, 0), (63, 0)]);
+
+        let mut clipped = processor(&rom);
+        clipped.quirks.sprite_clipping = true;
+        run(&mut clipped, 4).unwrap();
+        assert_eq!(clipped.lit_pixels().collect::<Vec<_>>(), [(62, 0), (63, 0)]);
+    }
+
+    #[test]
+    fn display_wait_quirk_stalls_until_tick() {
+        // DRW V0, V0, 1; LD V0, 0x01
+        let mut processor = processor(&[0xD0, 0x01, 0x60, 0x01]);
+        processor.quirks.display_wait = true;
+        run(&mut processor, 1).unwrap();
+        assert!(processor.is_waiting_for_vblank());
+        run(&mut processor, 3).unwrap();
+        assert_eq!(processor.program_counter, 0x202);
+        assert_eq!(processor.registers[0], 0);
+
+        processor.tick_timers();
+        assert!(!processor.is_waiting_for_vblank());
+        run(&mut processor, 1).unwrap();
+        assert_eq!(processor.program_counter, 0x204);
+        assert_eq!(processor.registers[0], 1);
+    }
+
+    #[test]
+    fn sprite_reads_wrap_past_end_of_memory() {
+        // LD I, 0xFFF; DRW V0, V0, 8
+        let mut processor = processor(&[0xAF, 0xFF, 0xD0, 0x08]);
+        processor.memory[0xFFF] = 0x80;
+        processor.log_memory_access = true;
+        run(&mut processor, 2).unwrap();
+        // The last byte of memory is followed by the font at address 0: 0xF0, 0x90, 0x90, ...
+        assert!(processor.get_pixel(0, 0) && !processor.get_pixel(1, 0));
+        assert!((0..4).all(|x| processor.get_pixel(x, 1)));
+        assert!(processor.get_pixel(0, 2) && processor.get_pixel(3, 2));
+        let log = processor.take_memory_log();
+        let addresses: Vec<_> = log.iter().map(|access| access.address).collect();
+        assert_eq!(addresses, [0xFFF, 0, 1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn seventeen_nested_calls_overflow_stack() {
+        // Each CALL calls the next one.
+        let rom: Vec<u8> = (0..17u16)
+            .flat_map(|i| {
+                let opcode = 0x2202 + 2 * i;
+                vec![(opcode >> 8) as u8, opcode as u8]
+            })
+            .collect();
+        let mut processor = processor(&rom);
+        run(&mut processor, 16).unwrap();
+        assert_eq!(processor.stack_pointer, 16);
+        match run(&mut processor, 1) {
+            Err(Error::StackOverflow { pc: 0x220 }) => {}
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn bare_return_underflows_stack() {
+        // RET
+        let mut processor = processor(&[0x00, 0xEE]);
+        match run(&mut processor, 1) {
+            Err(Error::StackUnderflow { pc: 0x200 }) => {}
+            result => panic!("{:?}", result),
+        }
+        assert_eq!(processor.stack_pointer, 0);
+    }
+
+    #[test]
+    fn errors_display_their_details() {
+        // `From<String>` is kept for code written against the old `Error(String)`.
+        let error = Error::from(String::from("custom"));
+        assert_eq!(error.to_string(), "custom");
+        let error = Error::UnknownOpcode {
+            pc: 0x202,
+            opcode: 0x800F,
+        };
+        assert_eq!(error.to_string(), "Unknown opcode at 0x202: 0x800F.");
+        let error = Error::MemoryOutOfBounds { addr: 0x1000 };
+        assert_eq!(error.to_string(), "Address out of bounds: 0x1000.");
+    }
+
+    #[test]
+    fn reset_restores_initial_state() {
+        // LD V0, 0x09; LD ST, V0; LD DT, V0; LD I, 0x300; DRW V0, V0, 5; CALL 0x20C; SHL V0, V0
+        let rom = [
+            0x60, 0x09, 0xF0, 0x18, 0xF0, 0x15, 0xA3, 0x00, 0xD0, 0x05, 0x22, 0x0C, 0x80, 0x0E,
+        ];
+        let mut mutated = processor(&rom);
+        run(&mut mutated, 7).unwrap();
+        mutated.set_key(3, true);
+        assert_ne!(mutated.state(), processor(&rom).state());
+
+        mutated.reset();
+        assert_eq!(mutated.state(), processor(&rom).state());
+        assert_eq!(mutated.cycles(), 0);
+        assert_eq!(&mutated.memory[0x200..0x200 + rom.len()], &rom);
+    }
+
+    #[test]
+    fn scroll_down_clears_top_rows() {
+        // SCD 3
+        let mut processor = processor(&[0x00, 0xC3]);
+        fill_display(&mut processor);
+        processor.set_pixel(5, 10, false);
+        run(&mut processor, 1).unwrap();
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let expected = y >= 3 && (x, y) != (5, 13);
+                assert_eq!(processor.get_pixel(x, y), expected, "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn horizontal_scrolls_clear_edge_columns() {
+        // SCR and SCL, with the columns that are lit afterwards.
+        for &(opcode, columns) in &[(0xFB, [4, 14]), (0xFC, [6, 59])] {
+            let mut processor = processor(&[0x00, opcode]);
+            for y in 0..HEIGHT {
+                for &x in &[0, 10, 63] {
+                    processor.set_pixel(x, y, true);
+                }
+            }
+            run(&mut processor, 1).unwrap();
+            for y in 0..HEIGHT {
+                for x in 0..WIDTH {
+                    assert_eq!(processor.get_pixel(x, y), columns.contains(&x), "{:02X}", opcode);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn exit_halts_processor() {
+        // EXIT; LD V0, 0x01
+        let mut processor = processor(&[0x00, 0xFD, 0x60, 0x01]);
+        assert_eq!(run(&mut processor, 1).unwrap(), CycleEvent::Halted);
+        assert!(processor.is_halted());
+        let state = processor.state();
+        assert_eq!(run(&mut processor, 5).unwrap(), CycleEvent::Halted);
+        assert_eq!(processor.state(), state);
+        assert_eq!(processor.registers[0], 0);
+    }
+
+    #[test]
+    fn resolution_switch_changes_drawing_area() {
+        // HIGH; LD V0, 100; LD V1, 50; DRW V0, V1, 1; LOW; DRW V0, V1, 1
+        let mut processor =
+            processor(&[0x00, 0xFF, 0x60, 0x64, 0x61, 0x32, 0xD0, 0x11, 0x00, 0xFE, 0xD0, 0x11]);
+        assert_eq!((processor.width(), processor.height()), (WIDTH, HEIGHT));
+        run(&mut processor, 4).unwrap();
+        assert!(processor.is_hires());
+        assert_eq!((processor.width(), processor.height()), (HIRES_WIDTH, HIRES_HEIGHT));
+        let lit: Vec<_> = processor.lit_pixels().collect();
+        assert_eq!(lit, [(100, 50), (101, 50), (102, 50), (103, 50)]);
+
+        // The coordinates wrap around the smaller display.
+        run(&mut processor, 2).unwrap();
+        assert_eq!((processor.width(), processor.height()), (WIDTH, HEIGHT));
+        let lit: Vec<_> = processor.lit_pixels().collect();
+        assert_eq!(lit, [(36, 18), (37, 18), (38, 18), (39, 18)]);
+    }
+
+    #[test]
+    fn large_sprite_draws_16x16_block() {
+        // HIGH; LD I, 0x300; DRW V0, V0, 0; DRW V0, V0, 0
+        let mut processor = processor(&[0x00, 0xFF, 0xA3, 0x00, 0xD0, 0x00, 0xD0, 0x00]);
+        for byte in &mut processor.memory[0x300..0x320] {
+            *byte = 0xFF;
+        }
+        run(&mut processor, 3).unwrap();
+        let lit: Vec<_> = processor.lit_pixels().collect();
+        let block: Vec<_> = (0..16).flat_map(|y| (0..16).map(move |x| (x, y))).collect();
+        assert_eq!(lit, block);
+        assert_eq!(processor.registers[0xF], 0);
+
+        run(&mut processor, 1).unwrap();
+        assert_eq!(processor.lit_pixels().count(), 0);
+        assert_eq!(processor.registers[0xF], 1);
+    }
+
+    #[test]
+    fn large_sprite_draws_nothing_in_low_resolution() {
+        // LD VF, 0x01; LD I, 0x300; DRW V0, V0, 0
+        let mut chip8 = processor(&[0x6F, 0x01, 0xA3, 0x00, 0xD0, 0x00]);
+        for byte in &mut chip8.memory[0x300..0x320] {
+            *byte = 0xFF;
+        }
+        chip8.set_pixel(0, 0, true);
+        run(&mut chip8, 3).unwrap();
+        let lit: Vec<_> = chip8.lit_pixels().collect();
+        assert_eq!(lit, [(0, 0)]);
+        assert_eq!(chip8.registers[0xF], 0);
+
+        // XO-CHIP draws a 16x16 sprite in low resolution too.
+        // LD I, 0x300; DRW V0, V0, 0
+        let mut xochip = processor(&[0xA3, 0x00, 0xD0, 0x00]);
+        xochip.set_xochip(true);
+        for byte in &mut xochip.memory[0x300..0x320] {
+            *byte = 0xFF;
+        }
+        run(&mut xochip, 2).unwrap();
+        assert_eq!(xochip.lit_pixels().count(), 16 * 16);
+    }
+
+    #[test]
+    fn high_font_points_at_ten_byte_glyphs() {
+        for &digit in &[0u8, 7, 9] {
+            // LD V0, digit; LD HF, V0
+            let mut processor = processor(&[0x60, digit, 0xF0, 0x30]);
+            run(&mut processor, 2).unwrap();
+            let address = FONTSET_HIGH_ADDRESS + 10 * digit as usize;
+            assert_eq!(processor.index, address);
+            assert_eq!(
+                &processor.memory[address..address + 10],
+                &FONTSET_HIGH[10 * digit as usize..10 * digit as usize + 10]
+            );
+        }
+    }
+
+    #[test]
+    fn rpl_flags_round_trip_registers() {
+        // LD R, V3; LD V3, R
+        let mut processor = processor(&[0xF3, 0x75, 0xF3, 0x85]);
+        processor.registers[..5].copy_from_slice(&[1, 2, 3, 4, 5]);
+        run(&mut processor, 1).unwrap();
+        assert_eq!(processor.rpl_flags[..5], [1, 2, 3, 4, 0]);
+
+        processor.registers = [0xEE; 16];
+        run(&mut processor, 1).unwrap();
+        assert_eq!(processor.registers[..5], [1, 2, 3, 4, 0xEE]);
+    }
+
+    #[test]
+    fn xochip_planes_are_drawn_independently() {
+        // PLANE 1; DRW V0, V0, 1; PLANE 2; LD V1, 0x01; DRW V0, V1, 1; PLANE 3; DRW V0, V0, 1
+        let rom = [
+            0xF1, 0x01, 0xD0, 0x01, 0xF2, 0x01, 0x61, 0x01, 0xD0, 0x11, 0xF3, 0x01, 0xD0, 0x01,
+        ];
+        let mut processor = processor(&rom);
+        processor.set_xochip(true);
+        run(&mut processor, 2).unwrap();
+        assert_eq!((processor.pixel_colour(0, 0), processor.pixel_colour(0, 1)), (1, 0));
+        run(&mut processor, 3).unwrap();
+        assert_eq!((processor.pixel_colour(0, 0), processor.pixel_colour(0, 1)), (1, 2));
+
+        // Each plane reads its own byte of the font: 0xF0 erases the first plane, and 0x90 is drawn
+        // into the second.
+        run(&mut processor, 2).unwrap();
+        let colours: Vec<_> = (0..4).map(|x| processor.pixel_colour(x, 0)).collect();
+        assert_eq!(colours, [2, 0, 0, 2]);
+    }
+
+    #[test]
+    fn plane_selection_limits_drawing() {
+        // PLANE 2; LD V0, 0x08; DRW V0, V0, 5
+        let mut processor = processor(&[0xF2, 0x01, 0x60, 0x08, 0xD0, 0x05]);
+        processor.set_xochip(true);
+        processor.set_pixel(1, 1, true);
+        let before = processor.state();
+        run(&mut processor, 3).unwrap();
+        let after = processor.state();
+        assert_eq!(processor.selected_planes, 2);
+        assert_eq!(after.display, before.display);
+        assert_ne!(after.second_plane, before.second_plane);
+        assert_eq!(after.second_plane.iter().filter(|&&pixel| pixel).count(), 14);
+    }
+
+    #[test]
+    fn drawing_with_no_plane_selected_does_nothing() {
+        // PLANE 0; LD I, 0x300; DRW V0, V0, 5
+        let mut processor = processor(&[0xF0, 0x01, 0xA3, 0x00, 0xD0, 0x05]);
+        processor.set_xochip(true);
+        for byte in &mut processor.memory[0x300..0x305] {
+            *byte = 0xFF;
+        }
+        processor.set_pixel(0, 0, true);
+        processor.registers[0xF] = 0x42;
+        processor.take_draw();
+        let before = processor.state();
+        assert_eq!(run(&mut processor, 3).unwrap(), CycleEvent::None);
+        let after = processor.state();
+        assert_eq!(processor.selected_planes, 0);
+        assert_eq!((after.display, after.second_plane), (before.display, before.second_plane));
+        assert_eq!(processor.registers[0xF], 0x42);
+        assert!(!processor.take_draw());
+    }
+
+    #[test]
+    fn audio_opcode_loads_pattern() {
+        // LD I, 0x300; AUDIO
+        let mut processor = processor(&[0xA3, 0x00, 0xF0, 0x02]);
+        processor.set_xochip(true);
+        let pattern: Vec<u8> = (0..16).map(|i| 0x11 * i).collect();
+        processor.memory[0x300..0x310].copy_from_slice(&pattern);
+        run(&mut processor, 2).unwrap();
+        assert_eq!(&processor.audio_buffer()[..], &pattern[..]);
+        assert_eq!(processor.index, 0x300);
+    }
+
+    #[test]
+    fn long_index_load_reads_next_word() {
+        // LD I, 0xBEEF; LD V0, [I]
+        let mut processor = processor(&[0xF0, 0x00, 0xBE, 0xEF, 0xF0, 0x65]);
+        processor.set_xochip(true);
+        assert_eq!(processor.memory.len(), XOCHIP_MEMORY_SIZE);
+        processor.memory[0xBEEF] = 0x42;
+        run(&mut processor, 1).unwrap();
+        assert_eq!(processor.index, 0xBEEF);
+        assert_eq!(processor.program_counter, 0x204);
+        run(&mut processor, 1).unwrap();
+        assert_eq!(processor.registers[0], 0x42);
+    }
+
+    #[test]
+    fn scroll_up_clears_bottom_rows() {
+        // SCU 4
+        let mut processor = processor(&[0x00, 0xD4]);
+        processor.set_xochip(true);
+        fill_display(&mut processor);
+        processor.set_pixel(9, 20, false);
+        run(&mut processor, 1).unwrap();
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let expected = y < HEIGHT - 4 && (x, y) != (9, 16);
+                assert_eq!(processor.get_pixel(x, y), expected, "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn beep_callback_reports_edges() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        // LD V0, 0x02; LD ST, V0; LD ST, V0
+        let mut processor = processor(&[0x60, 0x02, 0xF0, 0x18, 0xF0, 0x18]);
+        let edges = Rc::new(RefCell::new(Vec::new()));
+        let recorder = Rc::clone(&edges);
+        processor.set_beep_callback(move |beeping| recorder.borrow_mut().push(beeping));
+        run(&mut processor, 1).unwrap();
+        assert_eq!(run(&mut processor, 1).unwrap(), CycleEvent::BeepStart);
+        assert_eq!(*edges.borrow(), [true]);
+
+        // Setting the timer again while beeping is not an edge.
+        run(&mut processor, 1).unwrap();
+        processor.tick_timers();
+        assert_eq!(*edges.borrow(), [true]);
+        processor.tick_timers();
+        assert_eq!(*edges.borrow(), [true, false]);
+        assert!(!processor.should_beep());
+    }
+
+    #[test]
+    fn fill_audio_is_silent_unless_beeping() {
+        let mut processor = Processor::with_seed(0);
+        let mut samples = [1.0; 256];
+        processor.fill_audio(&mut samples, 44_100);
+        assert!(samples.iter().all(|&sample| sample == 0.0));
+
+        processor.sound_timer = 5;
+        processor.fill_audio(&mut samples, 44_100);
+        assert!(samples.iter().all(|&sample| sample != 0.0));
+        // Half a period of a 440 Hz square wave is just over 50 samples.
+        assert!(samples[..51].iter().all(|&sample| sample > 0.0));
+        assert!(samples[51] < 0.0);
+    }
+
+    #[test]
+    fn step_does_not_tick_timers() {
+        // LD V0, 0x01; JP 0x202
+        let mut processor = processor(&[0x60, 0x01, 0x12, 0x02]);
+        processor.delay_timer = 30;
+        processor.sound_timer = 30;
+        run(&mut processor, 10).unwrap();
+        assert_eq!((processor.delay_timer, processor.sound_timer), (30, 30));
+        processor.tick_timers();
+        assert_eq!((processor.delay_timer, processor.sound_timer), (29, 29));
+    }
+
+    #[test]
+    fn breakpoint_stops_before_instruction() {
+        // LD V0, 0x01; LD V1, 0x02; LD V2, 0x03
+        let mut processor = processor(&[0x60, 0x01, 0x61, 0x02, 0x62, 0x03]);
+        processor.add_breakpoint(0x204);
+        assert_eq!(run(&mut processor, 2).unwrap(), CycleEvent::None);
+        assert_eq!(processor.step().unwrap(), CycleEvent::Breakpoint(0x204));
+        assert_eq!(processor.program_counter, 0x204);
+        assert_eq!(processor.registers[2], 0);
+
+        // The next step continues past the breakpoint.
+        assert_eq!(processor.step().unwrap(), CycleEvent::None);
+        assert_eq!(processor.registers[2], 3);
+    }
+
+    #[test]
+    fn trace_callback_receives_executed_instructions() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        // CALL 0x206; JP 0x204; JP 0x204; RET
+        let mut processor = processor(&[0x22, 0x06, 0x12, 0x04, 0x12, 0x04, 0x00, 0xEE]);
+        let trace = Rc::new(RefCell::new(Vec::new()));
+        let recorder = Rc::clone(&trace);
+        processor.set_trace_callback(move |pc, opcode| recorder.borrow_mut().push((pc, opcode)));
+        run(&mut processor, 4).unwrap();
+        assert_eq!(
+            *trace.borrow(),
+            [(0x200, 0x2206), (0x206, 0x00EE), (0x202, 0x1204), (0x204, 0x1204)]
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn save_state_round_trips() {
+        // LD V0, 0x07; LD I, 0x300; DRW V0, V0, 5; CALL 0x20A; LD DT, V0
+        let mut saved = processor(&[0x60, 0x07, 0xA3, 0x00, 0xD0, 0x05, 0x22, 0x0A, 0xF0, 0x15]);
+        saved.quirks = ProcessorConfig::schip().quirks;
+        saved.present_mode = PresentMode::Manual;
+        saved.rpl_flags[3] = 9;
+        saved.set_key(0xB, true);
+        run(&mut saved, 5).unwrap();
+        let bytes = saved.save_state();
+
+        let mut loaded = Processor::new();
+        loaded.load_state(&bytes).unwrap();
+        assert_eq!(loaded.state(), saved.state());
+        assert_eq!(loaded.quirks, saved.quirks);
+        assert_eq!(loaded.present_mode, saved.present_mode);
+        assert_eq!(loaded.draw_mode, saved.draw_mode);
+        assert_eq!(loaded.rpl_flags, saved.rpl_flags);
+        assert_eq!(loaded.keypad, saved.keypad);
+        assert_eq!(loaded.cycles(), 5);
+
+        match loaded.load_state(&bytes[..10]) {
+            Err(Error::InvalidSaveState { .. }) => {}
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn presets_set_platform_quirks() {
+        let chip8 = ProcessorConfig::chip8();
+        assert!(chip8.quirks.shift_uses_vy && chip8.quirks.logic_resets_vf);
+        assert!(chip8.quirks.display_wait && chip8.quirks.wait_for_key_release);
+        assert!(chip8.quirks.sprite_clipping && !chip8.quirks.jump_uses_vx);
+        assert_eq!(chip8.quirks.index_increment, IndexIncrement::ByXPlus1);
+        assert!(!chip8.xochip);
+
+        let schip = ProcessorConfig::schip();
+        assert!(schip.quirks.jump_uses_vx && schip.quirks.sprite_clipping);
+        assert!(!schip.quirks.shift_uses_vy && !schip.quirks.display_wait);
+        assert_eq!(schip.quirks.index_increment, IndexIncrement::None);
+        assert!(!schip.xochip);
+
+        let xochip = ProcessorConfig::xochip();
+        assert!(xochip.quirks.shift_uses_vy && !xochip.quirks.sprite_clipping);
+        assert_eq!(xochip.quirks.index_increment, IndexIncrement::ByXPlus1);
+        assert!(xochip.xochip);
+
+        let mut processor = ProcessorConfig {
+            rng_seed: Some(7),
+            ..xochip
+        }
+        .build();
+        assert_eq!(processor.quirks, xochip.quirks);
+        assert_eq!(processor.memory.len(), XOCHIP_MEMORY_SIZE);
+        assert_eq!(processor.random_byte(), Processor::with_seed(7).random_byte());
+    }
+
+    #[test]
+    fn display_bytes_packs_rows() {
+        let mut processor = Processor::with_seed(0);
+        for &(x, y) in &[(0, 0), (7, 0), (8, 0), (63, 0), (9, 1), (62, 31)] {
+            processor.set_pixel(x, y, true);
+        }
+        let bytes = processor.display_bytes();
+        assert_eq!(&bytes[..8], &[0x81, 0x80, 0, 0, 0, 0, 0, 0x01]);
+        assert_eq!(&bytes[8..16], &[0, 0x40, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(bytes[bytes.len() - 1], 0x02);
+        assert_eq!(bytes.iter().map(|byte| byte.count_ones()).sum::<u32>(), 6);
+        #[allow(deprecated)]
+        let display = processor.display();
+        assert_eq!(display, processor.framebuffer().2);
+
+        let mut hires = Processor::with_seed(0);
+        // HIGH
+        hires.load_file(&[0x00, 0xFF]).unwrap();
+        hires.step().unwrap();
+        for &(x, y) in &[(0, 0), (1, 1), (3, 0), (127, 63)] {
+            hires.set_pixel(x, y, true);
+        }
+        let bytes = hires.display_bytes();
+        assert_eq!(&bytes[..8], &[0xC0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(bytes[bytes.len() - 1], 0x01);
+        assert_eq!(bytes.iter().map(|byte| byte.count_ones()).sum::<u32>(), 3);
+    }
+
+    #[test]
+    fn lit_pixels_are_yielded_row_by_row() {
+        let mut processor = Processor::with_seed(0);
+        for &(x, y) in &[(40, 20), (3, 5), (10, 5)] {
+            processor.set_pixel(x, y, true);
+        }
+        let lit: Vec<_> = processor.lit_pixels().collect();
+        assert_eq!(lit, [(3, 5), (10, 5), (40, 20)]);
+    }
+
+    #[test]
+    fn key_mask_round_trips() {
+        let mut processor = Processor::with_seed(0);
+        for &mask in &[0x0000, 0x8001, 0x1234, 0xFFFF] {
+            processor.set_keys(mask);
+            assert_eq!(processor.keys(), mask);
+        }
+        processor.set_keys(0x0020);
+        assert!(processor.keypad[5]);
+        processor.set_key(5, false);
+        assert_eq!(processor.keys(), 0);
+    }
+
+    #[test]
+    fn take_draw_consumes_draw_flag() {
+        // CLS
+        let mut processor = processor(&[0x00, 0xE0]);
+        processor.present_mode = PresentMode::Immediate;
+        processor.mark_presented();
+        run(&mut processor, 1).unwrap();
+        assert!(processor.take_draw());
+        assert!(!processor.take_draw());
+        assert!(!processor.needs_present());
+    }
+
+    #[test]
+    fn run_frame_executes_cycles_then_ticks() {
+        // LD V0, 0x03; LD DT, V0; ADD V1, 0x01 (x4)
+        let mut processor =
+            processor(&[0x60, 0x03, 0xF0, 0x15, 0x71, 0x01, 0x71, 0x01, 0x71, 0x01, 0x71, 0x01]);
+        assert_eq!(processor.run_frame(5).unwrap(), []);
+        assert_eq!(processor.program_counter, 0x20A);
+        assert_eq!(processor.registers[1], 3);
+        assert_eq!(processor.delay_timer, 2);
+    }
+
+    #[test]
+    fn run_frame_reports_every_event() {
+        // LD V0, 0x02; LD F, V0; DRW V0, V0, 5; LD ST, V0; DRW V0, V0, 5; EXIT
+        let rom = [0x60, 0x02, 0xF0, 0x29, 0xD0, 0x05, 0xF0, 0x18, 0xD0, 0x05, 0x00, 0xFD];
+        let mut beeping = processor(&rom);
+        assert_eq!(beeping.run_frame(3).unwrap(), [CycleEvent::Draw]);
+        assert_eq!(beeping.run_frame(2).unwrap(), [CycleEvent::BeepStart, CycleEvent::Draw]);
+        assert_eq!(beeping.run_frame(1).unwrap(), [CycleEvent::Halted, CycleEvent::BeepStop]);
+
+        // LD V0, 0x02; LD F, V0; DRW V0, V0, 5; JP 0x206
+        let mut stopped = processor(&[0x60, 0x02, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x06]);
+        stopped.add_breakpoint(0x206);
+        assert_eq!(
+            stopped.run_frame(10).unwrap(),
+            [CycleEvent::Draw, CycleEvent::Breakpoint(0x206)]
+        );
+        assert_eq!(stopped.program_counter, 0x206);
+    }
+
+    #[test]
+    fn run_for_executes_headless() {
+        // LD V0, 0x00; ADD V0, 0x01; JP 0x202
+        let mut processor = processor(&[0x60, 0x00, 0x70, 0x01, 0x12, 0x02]);
+        processor.run_for(100).unwrap();
+        assert_eq!(processor.registers[0], 50);
+        assert_eq!(processor.cycles(), 100);
+    }
+
+    #[test]
+    fn pixel_access_at_corner_and_out_of_bounds() {
+        let mut processor = Processor::with_seed(0);
+        processor.set_pixel(WIDTH - 1, HEIGHT - 1, true);
+        assert!(processor.get_pixel(WIDTH - 1, HEIGHT - 1));
+        assert_eq!(processor.lit_pixels().collect::<Vec<_>>(), [(63, 31)]);
+
+        processor.set_pixel(WIDTH, 0, true);
+        processor.set_pixel(0, HEIGHT, true);
+        assert!(!processor.get_pixel(WIDTH, 0));
+        assert!(!processor.get_pixel(0, HEIGHT));
+        assert_eq!(processor.lit_pixels().count(), 1);
+
+        processor.set_pixel(WIDTH - 1, HEIGHT - 1, false);
+        assert!(!processor.get_pixel(WIDTH - 1, HEIGHT - 1));
+        processor.set_pixel(0, 0, true);
+        processor.clear_display();
+        assert_eq!(processor.lit_pixels().count(), 0);
+    }
+
+    #[test]
+    fn opcode_stats_count_classes() {
+        // LD V0, 0x00; ADD V0, 0x01; SE V0, 0x05; JP 0x202; JP 0x208
+        let mut processor =
+            processor(&[0x60, 0x00, 0x70, 0x01, 0x30, 0x05, 0x12, 0x02, 0x12, 0x08]);
+        processor.profile_opcodes = true;
+        run(&mut processor, 18).unwrap();
+        let stats = processor.opcode_stats();
+        assert_eq!(stats.classes[0x1], 7);
+        assert_eq!(stats.classes[0x3], 5);
+        assert_eq!(stats.classes[0x6], 1);
+        assert_eq!(stats.classes[0x7], 5);
+        assert_eq!(stats.total(), 18);
+    }
+
+    #[test]
+    fn recorded_input_replays_to_same_display() {
+        // LD V0, K; LD F, V0; DRW V1, V2, 5; ADD V1, 0x05; JP 0x200
+        let rom = [0xF0, 0x0A, 0xF0, 0x29, 0xD1, 0x25, 0x71, 0x05, 0x12, 0x00];
+        let changes = [(5, 1 << 0x3), (20, 1 << 0xA), (35, 0)];
+        let mut recorded = processor(&rom);
+        recorded.record_input();
+        for cycle in 0..50 {
+            if let Some(&(_, mask)) = changes.iter().find(|&&(at, _)| at == cycle) {
+                recorded.set_keys(mask);
+            }
+            recorded.step().unwrap();
+        }
+        let log = recorded.input_log();
+        assert_eq!(log.keys, [(0, 0), (5, 0x0008), (20, 0x0400), (35, 0)]);
+
+        let mut replayed = processor(&rom);
+        replayed.play_input(log);
+        run(&mut replayed, 50).unwrap();
+        assert_eq!(replayed.state(), recorded.state());
+        assert!(replayed.lit_pixels().count() > 0);
+    }
+
+    #[test]
+    fn permissive_mode_skips_unknown_opcodes() {
+        // 8xyF; LD V0, 0x01
+        let rom = [0x81, 0x2F, 0x60, 0x01];
+        let mut strict = processor(&rom);
+        match run(&mut strict, 1) {
+            Err(Error::UnknownOpcode { pc: 0x200, opcode: 0x812F }) => {}
+            result => panic!("{:?}", result),
+        }
+
+        let mut permissive = processor(&rom);
+        permissive.quirks.skip_unknown_opcodes = true;
+        run(&mut permissive, 2).unwrap();
+        assert_eq!(permissive.program_counter, 0x204);
+        assert_eq!(permissive.registers[..3], [1, 0, 0]);
+    }
+
+    #[test]
+    fn step_back_undoes_instructions() {
+        // LD V0, 0x01; ADD V0, 0x01; ADD V0, 0x01
+        let mut processor = processor(&[0x60, 0x01, 0x70, 0x01, 0x70, 0x01]);
+        processor.record_history = true;
+        run(&mut processor, 3).unwrap();
+        assert_eq!((processor.program_counter, processor.registers[0]), (0x206, 3));
+
+        assert!(processor.step_back());
+        assert!(processor.step_back());
+        assert_eq!((processor.program_counter, processor.registers[0]), (0x202, 1));
+        assert!(processor.record_history);
+        assert!(processor.step_back());
+        assert!(!processor.step_back());
+        assert_eq!(processor.program_counter, 0x200);
+    }
+
+    #[test]
+    fn key_wait_quirk_waits_for_release() {
+        for &wait_for_release in &[false, true] {
+            // LD V3, K
+            let mut processor = processor(&[0xF3, 0x0A]);
+            processor.quirks.wait_for_key_release = wait_for_release;
+            run(&mut processor, 2).unwrap();
+            assert_eq!(processor.program_counter, 0x200);
+
+            processor.set_key(0xC, true);
+            run(&mut processor, 1).unwrap();
+            if wait_for_release {
+                assert_eq!(processor.program_counter, 0x200);
+                processor.set_key(0xC, false);
+                run(&mut processor, 1).unwrap();
+            }
+            assert_eq!(processor.program_counter, 0x202);
+            assert_eq!(processor.registers[3], 0xC);
+        }
+    }
+
+    #[test]
+    fn logic_quirk_resets_vf() {
+        for &resets in &[false, true] {
+            // LD VF, 0x01; LD V0, 0x0C; LD V1, 0x0A; AND V0, V1
+            let mut processor = processor(&[0x6F, 0x01, 0x60, 0x0C, 0x61, 0x0A, 0x80, 0x12]);
+            processor.quirks.logic_resets_vf = resets;
+            run(&mut processor, 4).unwrap();
+            assert_eq!(processor.registers[0], 0x08);
+            assert_eq!(processor.registers[0xF], if resets { 0 } else { 1 });
+        }
+    }
+
+    #[test]
+    fn step_reports_events() {
+        // CLS; LD V0, 0x01; LD ST, V0; EXIT
+        let mut processor = processor(&[0x00, 0xE0, 0x60, 0x01, 0xF0, 0x18, 0x00, 0xFD]);
+        assert_eq!(processor.step().unwrap(), CycleEvent::Draw);
+        assert_eq!(processor.step().unwrap(), CycleEvent::None);
+        assert_eq!(processor.step().unwrap(), CycleEvent::BeepStart);
+        assert_eq!(processor.step().unwrap(), CycleEvent::Halted);
+        assert_eq!(processor.step().unwrap(), CycleEvent::Halted);
+    }
+
+    #[test]
+    fn same_seed_and_inputs_give_equal_state() {
+        // RND V0, 0xFF; SKP V1; LD ST, V0; LD F, V0; DRW V0, V0, 5; JP 0x200
+        let rom = [0xC0, 0xFF, 0xE1, 0x9E, 0xF0, 0x18, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x00];
+        let runs: Vec<_> = (0..2)
+            .map(|_| {
+                let mut processor = processor(&rom);
+                for frame in 0..20 {
+                    processor.set_key(0, frame % 3 == 0);
+                    processor.run_frame(7).unwrap();
+                }
+                processor.state()
+            })
+            .collect();
+        assert_eq!(runs[0], runs[1]);
+    }
+
+    #[test]
+    fn self_jump_idles_while_timers_tick() {
+        // LD V0, 0x05; LD DT, V0; JP 0x204
+        let mut processor = processor(&[0x60, 0x05, 0xF0, 0x15, 0x12, 0x04]);
+        run(&mut processor, 2).unwrap();
+        assert!(!processor.is_idle());
+        run(&mut processor, 1).unwrap();
+        assert!(processor.is_idle());
+        for _ in 0..3 {
+            processor.run_frame(4).unwrap();
+        }
+        assert!(processor.is_idle());
+        assert_eq!(processor.program_counter, 0x204);
+        assert_eq!(processor.delay_timer, 2);
+    }
+
+    #[test]
+    fn display_diff_lists_toggled_pixels() {
+        let mut processor = Processor::with_seed(0);
+        processor.set_pixel(5, 0, true);
+        let (_, _, previous) = processor.framebuffer();
+        processor.set_pixel(5, 0, false);
+        processor.set_pixel(2, 3, true);
+        assert_eq!(processor.display_diff(&previous), [5, 3 * WIDTH + 2]);
+        assert_eq!(processor.display_diff(&processor.framebuffer().2), []);
+        assert_eq!(processor.display_diff(&[]).len(), WIDTH * HEIGHT);
+    }
+
+    #[test]
+    fn cycle_count_increments_per_step() {
+        // LD V0, 0x01; JP 0x200
+        let mut processor = processor(&[0x60, 0x01, 0x12, 0x00]);
+        for cycles in 1..=5 {
+            processor.step().unwrap();
+            assert_eq!(processor.cycles(), cycles);
+        }
+        processor.tick_timers();
+        assert_eq!(processor.cycles(), 5);
+        processor.reset();
+        assert_eq!(processor.cycles(), 0);
+    }
+
+    #[test]
+    fn run_until_stops_at_address() {
+        // LD V0, 0x00; ADD V0, 0x01; SE V0, 0x03; JP 0x202; JP 0x208
+        let mut processor =
+            processor(&[0x60, 0x00, 0x70, 0x01, 0x30, 0x03, 0x12, 0x02, 0x12, 0x08]);
+        assert!(processor.run_until(0x208, 100).unwrap());
+        assert_eq!(processor.program_counter, 0x208);
+        assert_eq!(processor.registers[0], 3);
+
+        // The address is never reached again within the limit.
+        assert!(!processor.run_until(0x202, 10).unwrap());
+        assert_eq!(processor.program_counter, 0x208);
+    }
+
+    #[test]
+    fn hexdump_formats_font() {
+        let processor = Processor::with_seed(0);
+        assert_eq!(
+            processor.hexdump(0, 16),
+            "0000  F0 90 90 90 F0 20 60 20 20 70 F0 10 F0 80 F0 F0  |..... `  p......|\n"
+        );
+        assert_eq!(
+            processor.hexdump(0xFFE, 16),
+            "0FFE  00 00                                            |..|\n"
+        );
+        assert_eq!(processor.hexdump(0x1000, 16), "");
     }
 }
@@ -1,41 +1,717 @@
+extern crate chip_8;
 extern crate glutin;
+#[cfg(feature = "serde")]
+extern crate serde_json;
 
+#[cfg(feature = "gamepad")]
+mod gamepad;
 mod graphics;
-pub mod lib;
+mod keymap;
+mod renderer;
+#[cfg(feature = "screenshot")]
+mod screenshot;
+#[cfg(unix)]
+mod terminal;
 
 use self::graphics::Graphics;
-use self::lib::{Processor, HEIGHT, WIDTH};
+use self::keymap::Keymap;
+use self::renderer::{Colour, PLANE_COLOURS};
+use chip_8::{CycleEvent, Processor, ProcessorState, HEIGHT, WIDTH};
 use glutin::GlContext;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::convert::TryFrom;
 use std::fs::File;
+use std::io;
 use std::io::prelude::*;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// The number of instructions kept in the trace written to crash dumps.
+const TRACE_LENGTH: usize = 32;
+
+/// The most recently executed instructions, as pairs of the program counter and the opcode,
+/// oldest first.
+type Trace = Rc<RefCell<VecDeque<(usize, u16)>>>;
+
+/// The command-line options.
+struct Options {
+    /// The ROM file to run.
+    file: Option<String>,
+    /// The name of a built-in ROM to run instead of a file.
+    builtin: Option<String>,
+    /// The two ROM files whose disassemblies to compare, instead of running a ROM.
+    diff_roms: Option<(String, String)>,
+    /// The number of instructions executed per second.
+    speed: u32,
+    /// The maximum number of frames presented per second.
+    fps: Option<u32>,
+    /// Whether to synchronise presenting frames with the monitor's refresh rate.
+    vsync: bool,
+    /// The file to write a crash dump to when the processor fails.
+    crash_dump: String,
+    /// The file to read the key mapping from.
+    keymap: Option<String>,
+    /// Whether to run in the terminal instead of a window.
+    tui: bool,
+    /// The number of frames to run for without a window or input, instead of opening a window.
+    headless: Option<u32>,
+    /// Whether to skip unknown opcodes instead of crashing.
+    permissive: bool,
+    /// Whether to choose the quirks from the instructions the ROM uses.
+    auto: bool,
+    /// Whether to enable the debugging features.
+    debug: bool,
+    /// The number of instructions executed per second while N is held, when paused.
+    step_repeat: u32,
+    /// The size of a low-resolution pixel in the window, in logical pixels.
+    scale: u32,
+    /// The colour of set pixels.
+    foreground: Colour,
+    /// The colour of clear pixels.
+    background: Colour,
+}
+
+impl Options {
+    /// Parse the options from the command-line arguments, excluding the program name.
+    fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+        let mut options = Options {
+            file: None,
+            builtin: None,
+            diff_roms: None,
+            speed: 700,
+            fps: None,
+            vsync: true,
+            crash_dump: String::from("chip-8-crash.txt"),
+            keymap: None,
+            tui: false,
+            headless: None,
+            permissive: false,
+            auto: false,
+            debug: false,
+            step_repeat: 10,
+            scale: 10,
+            foreground: PLANE_COLOURS[1],
+            background: PLANE_COLOURS[0],
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--builtin" => {
+                    options.builtin = Some(args.next().ok_or("--builtin requires a name.")?);
+                }
+                "--diff-roms" => {
+                    let old = args.next().ok_or("--diff-roms requires two files.")?;
+                    let new = args.next().ok_or("--diff-roms requires two files.")?;
+                    options.diff_roms = Some((old, new));
+                }
+                "--speed" => {
+                    let speed = args.next().ok_or("--speed requires a number.")?;
+                    match speed.parse() {
+                        Ok(speed) if speed > 0 => options.speed = speed,
+                        _ => return Err(format!("Invalid speed: {}.", speed)),
+                    }
+                }
+                "--fps" => {
+                    let fps = args.next().ok_or("--fps requires a number.")?;
+                    match fps.parse() {
+                        Ok(fps) if fps > 0 => options.fps = Some(fps),
+                        _ => return Err(format!("Invalid frame rate: {}.", fps)),
+                    }
+                }
+                "--headless" => {
+                    let frames = args.next().ok_or("--headless requires a number.")?;
+                    match frames.parse() {
+                        Ok(frames) => options.headless = Some(frames),
+                        _ => return Err(format!("Invalid number of frames: {}.", frames)),
+                    }
+                }
+                "--step-repeat" => {
+                    let rate = args.next().ok_or("--step-repeat requires a number.")?;
+                    match rate.parse() {
+                        Ok(rate) if rate > 0 => options.step_repeat = rate,
+                        _ => return Err(format!("Invalid step repeat rate: {}.", rate)),
+                    }
+                }
+                "--scale" => {
+                    let scale = args.next().ok_or("--scale requires a number.")?;
+                    match scale.parse() {
+                        Ok(scale) if scale > 0 => options.scale = scale,
+                        _ => return Err(format!("Invalid scale: {}.", scale)),
+                    }
+                }
+                "--fg" => {
+                    let colour = args.next().ok_or("--fg requires a colour.")?;
+                    options.foreground = parse_colour(&colour)?;
+                }
+                "--bg" => {
+                    let colour = args.next().ok_or("--bg requires a colour.")?;
+                    options.background = parse_colour(&colour)?;
+                }
+                "--keymap" => {
+                    options.keymap = Some(args.next().ok_or("--keymap requires a path.")?);
+                }
+                "--no-vsync" => options.vsync = false,
+                "--tui" => options.tui = true,
+                "--permissive" => options.permissive = true,
+                "--auto" => options.auto = true,
+                "--debug" => options.debug = true,
+                "--crash-dump" => {
+                    options.crash_dump = args.next().ok_or("--crash-dump requires a path.")?;
+                }
+                _ if arg.starts_with("--") => return Err(format!("Unknown option {}.", arg)),
+                _ => options.file = Some(arg),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Limits how often frames are presented, independently of the emulation speed.
+struct FrameLimiter {
+    /// The time between two frames.
+    frame_duration: Duration,
+    /// The earliest time at which the next frame may be presented.
+    next_frame: Instant,
+}
+
+impl FrameLimiter {
+    /// Create a `FrameLimiter` presenting at most `fps` frames per second.
+    fn new(fps: u32) -> FrameLimiter {
+        FrameLimiter {
+            frame_duration: Duration::from_secs(1) / fps,
+            next_frame: Instant::now(),
+        }
+    }
+
+    /// Return whether a frame may be presented now, and if so, schedule the next frame.
+    ///
+    /// This never blocks, so the processor keeps running while a frame is not due.
+    fn frame_due(&mut self) -> bool {
+        let now = Instant::now();
+        if now < self.next_frame {
+            return false;
+        }
+        self.next_frame += self.frame_duration;
+        // Don't try to catch up with frames missed while the display was not updated.
+        if self.next_frame < now {
+            self.next_frame = now + self.frame_duration;
+        }
+        true
+    }
+}
+
+/// Repeats an action at a fixed rate while its key is held, after an initial delay, like the key
+/// repeat of a keyboard.
+struct KeyRepeat {
+    /// The time between pressing the key and the first repeat.
+    delay: Duration,
+    /// The time between two repeats.
+    period: Duration,
+    /// When the action is next repeated, if the key is held.
+    next_repeat: Option<Instant>,
+}
+
+impl KeyRepeat {
+    /// Create a `KeyRepeat` repeating `rate` times per second after half a second.
+    fn new(rate: u32) -> KeyRepeat {
+        KeyRepeat {
+            delay: Duration::from_millis(500),
+            period: Duration::from_secs(1) / rate,
+            next_repeat: None,
+        }
+    }
+
+    /// Handle the key being pressed or released at `now`, returning whether to perform the action.
+    ///
+    /// The presses that the window system repeats while the key is held are ignored, so that the
+    /// rate is the same everywhere.
+    fn key(&mut self, pressed: bool, now: Instant) -> bool {
+        if !pressed {
+            self.next_repeat = None;
+            return false;
+        }
+        if self.next_repeat.is_some() {
+            return false;
+        }
+        self.next_repeat = Some(now + self.delay);
+        true
+    }
+
+    /// Return whether the action is due to be repeated at `now`, and if so, schedule the next
+    /// repeat.
+    fn repeat_due(&mut self, now: Instant) -> bool {
+        match self.next_repeat {
+            Some(next) if now >= next => {
+                // Don't try to catch up with repeats missed while not checking.
+                self.next_repeat = Some((next + self.period).max(now));
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Whether the processor is paused and the buzzer muted.
+///
+/// Pausing and muting can be toggled separately, or together with `toggle_pause_and_mute`. A pause
+/// started that way also unmutes when it ends, however it is resumed.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Playback {
+    /// Whether the processor is paused.
+    paused: bool,
+    /// Whether the buzzer is muted.
+    muted: bool,
+    /// Whether the buzzer was muted by `toggle_pause_and_mute`, so that resuming unmutes it.
+    muted_with_pause: bool,
+}
+
+impl Playback {
+    /// Pause or resume the processor.
+    fn toggle_pause(&mut self) {
+        if self.paused {
+            self.resume();
+        } else {
+            self.paused = true;
+        }
+    }
+
+    /// Mute or unmute the buzzer, independently of pausing.
+    fn toggle_mute(&mut self) {
+        self.muted = !self.muted;
+        self.muted_with_pause = false;
+    }
+
+    /// Pause the processor and mute the buzzer, or resume if paused.
+    ///
+    /// If the buzzer was already muted, it stays muted after resuming.
+    fn toggle_pause_and_mute(&mut self) {
+        if self.paused {
+            self.resume();
+        } else {
+            self.paused = true;
+            if !self.muted {
+                self.muted = true;
+                self.muted_with_pause = true;
+            }
+        }
+    }
+
+    /// Resume the processor, and unmute the buzzer if it was muted with the pause.
+    fn resume(&mut self) {
+        self.paused = false;
+        if self.muted_with_pause {
+            self.muted = false;
+            self.muted_with_pause = false;
+        }
+    }
+}
+
+/// Parse a colour written as six hexadecimal digits, `RRGGBB`, optionally preceded by `#`.
+fn parse_colour(text: &str) -> Result<Colour, String> {
+    let digits = text.trim_start_matches('#');
+    if digits.len() != 6 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Invalid colour: {}.", text));
+    }
+    // Unwrap is safe, because the digits were checked above.
+    let rgb = u32::from_str_radix(digits, 16).unwrap();
+    let component = |shift: u32| ((rgb >> shift) & 0xFF) as f32 / 255.0;
+    Ok((component(16), component(8), component(0)))
+}
+
+/// A command typed into the prompt opened with Tab when debugging.
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    /// Write a value to memory at an address.
+    Poke(usize, u8),
+    /// Execute a number of instructions, like pressing N as many times.
+    Step(usize),
+}
+
+/// Parse the text of the prompt: either `step <n>`, or an address and a value to poke as accepted
+/// by `parse_poke`. `step` on its own executes a single instruction.
+fn parse_command(text: &str) -> Result<Command, String> {
+    let mut words = text.split_whitespace();
+    if words.next() != Some("step") {
+        return parse_poke(text).map(|(address, value)| Command::Poke(address, value));
+    }
+    match (words.next(), words.next()) {
+        (None, _) => Ok(Command::Step(1)),
+        (Some(steps), None) => steps
+            .parse()
+            .map(Command::Step)
+            .map_err(|_| format!("invalid number of steps: {}", steps)),
+        _ => Err(String::from("expected a number of steps")),
+    }
+}
+
+/// Execute `steps` instructions with `run_cycle`, ticking the timers after each, until the program
+/// exits.
+fn step_cycles(processor: &mut Processor, steps: usize) -> Result<(), chip_8::Error> {
+    for _ in 0..steps {
+        if processor.run_cycle()? == CycleEvent::Halted {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Skip the unknown opcode at `pc` that stopped `processor`, so that execution continues with the
+/// next instruction.
+fn skip_unknown_opcode(processor: &mut Processor, pc: usize) {
+    processor.program_counter = pc + 2;
+}
+
+/// Parse an address and a value to poke, in hexadecimal and separated by whitespace (e.g.
+/// `"2A0 FF"`).
+fn parse_poke(text: &str) -> Result<(usize, u8), String> {
+    let mut words = text.split_whitespace();
+    let (address, value) = match (words.next(), words.next(), words.next()) {
+        (Some(address), Some(value), None) => (address, value),
+        _ => return Err(String::from("expected an address and a value")),
+    };
+    let address = usize::from_str_radix(address, 16)
+        .map_err(|_| format!("invalid address: {}", address))?;
+    let value = u8::from_str_radix(value, 16).map_err(|_| format!("invalid value: {}", value))?;
+    Ok((address, value))
+}
+
+/// Record the last `TRACE_LENGTH` instructions executed by `processor` into the returned `Trace`.
+fn record_trace(processor: &mut Processor) -> Trace {
+    let trace = Trace::default();
+    let recorder = Rc::clone(&trace);
+    processor.set_trace_callback(move |pc, opcode| {
+        let mut trace = recorder.borrow_mut();
+        if trace.len() == TRACE_LENGTH {
+            trace.pop_front();
+        }
+        trace.push_back((pc, opcode));
+    });
+    trace
+}
+
+/// Write the state of `processor` and the `trace` leading up to it failing with `error` to
+/// `path`, so that it can be attached to a bug report.
+fn write_crash_dump(
+    path: &str,
+    processor: &Processor,
+    trace: &Trace,
+    error: &chip_8::Error,
+) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    writeln!(file, "CHIP-8 crash dump")?;
+    writeln!(file, "Error: {}", error)?;
+
+    writeln!(file, "\n[state]")?;
+    write_state(&mut file, &processor.state())?;
+
+    // The last instruction is the one that failed.
+    writeln!(file, "\n[trace]")?;
+    for &(pc, opcode) in trace.borrow().iter() {
+        writeln!(file, "0x{:03X}: {:04X}  {}", pc, opcode, chip_8::disassemble(opcode))?;
+    }
+
+    writeln!(file, "\n[display]")?;
+    let (width, height, display) = processor.framebuffer();
+    for y in 0..height {
+        let row: String = (0..width)
+            .map(|x| if display[x + y * width] { '#' } else { '.' })
+            .collect();
+        writeln!(file, "{}", row)?;
+    }
+
+    Ok(())
+}
+
+/// Write `state` to `file` on a single line, as JSON.
+#[cfg(feature = "serde")]
+fn write_state(file: &mut File, state: &ProcessorState) -> io::Result<()> {
+    serde_json::to_writer(&mut *file, state)?;
+    writeln!(file)
+}
+
+/// Write `state` to `file` on a single line, in Rust's debug format. Build with the `serde` feature
+/// to write it as JSON instead.
+#[cfg(not(feature = "serde"))]
+fn write_state(file: &mut File, state: &ProcessorState) -> io::Result<()> {
+    writeln!(file, "{:?}", state)
+}
+
+/// Report that `processor` failed with `error`, write a crash dump with the `trace` to
+/// `crash_dump`, then exit.
+fn crash(crash_dump: &str, processor: &Processor, trace: &Trace, error: &chip_8::Error) -> ! {
+    eprintln!("Error: {}", error);
+    match write_crash_dump(crash_dump, processor, trace, error) {
+        Ok(()) => eprintln!("A crash dump was written to {}.", crash_dump),
+        Err(dump_error) => eprintln!("Error: could not write crash dump: {}", dump_error),
+    }
+    std::process::exit(1);
+}
+
+/// Run `processor` at `speed` instructions per second in the terminal instead of a window, until
+/// Escape or Ctrl-C is typed or the program exits.
+#[cfg(unix)]
+fn run_tui(
+    mut processor: Processor,
+    speed: u32,
+    crash_dump: &str,
+    trace: &Trace,
+    keymap: &Keymap,
+    colours: &[renderer::Colour; 4],
+) -> io::Result<()> {
+    let mut terminal = terminal::Terminal::new()?;
+
+    let cycle_period = Duration::from_secs(1) / speed;
+    let mut next_cycle = Instant::now();
+    let timer_period = Duration::from_secs(1) / 60;
+    let mut next_timer_tick = Instant::now() + timer_period;
+
+    while terminal.read_keys(keymap, &mut processor) && !processor.is_halted() {
+        while Instant::now() >= next_cycle && !processor.is_halted() {
+            if let Err(e) = processor.step() {
+                // Restore the terminal before reporting the error.
+                drop(terminal);
+                crash(crash_dump, &processor, trace, &e);
+            }
+            next_cycle += cycle_period;
+        }
+        if Instant::now() > next_cycle + timer_period {
+            next_cycle = Instant::now();
+        }
+
+        while Instant::now() >= next_timer_tick {
+            processor.tick_timers();
+            next_timer_tick += timer_period;
+        }
+
+        if processor.take_draw() {
+            renderer::draw(&mut terminal, &processor, colours, None);
+        }
+
+        if let Some(wait) = next_cycle.min(next_timer_tick).checked_duration_since(Instant::now()) {
+            std::thread::sleep(wait);
+        }
+    }
+
+    Ok(())
+}
+
+/// Run `processor` for `frames` 60 Hz frames of `speed` instructions per second, as quickly as
+/// possible and without a window or input, or until the program exits.
+fn run_headless(
+    mut processor: Processor,
+    frames: u32,
+    speed: u32,
+    crash_dump: &str,
+    trace: &Trace,
+) {
+    let cycles = Processor::hz_to_cycles_per_frame(speed) as usize;
+    for _ in 0..frames {
+        match processor.run_frame(cycles) {
+            Ok(ref events) if events.contains(&CycleEvent::Halted) => break,
+            Ok(_) => (),
+            Err(e) => crash(crash_dump, &processor, trace, &e),
+        }
+    }
+}
+
+/// Parse the contents of the keymap file `file`, given with its path, with `parse`.
+///
+/// If there is no keymap file or it could not be loaded, return `default()`.
+fn load_mapping<T>(
+    file: &Option<(&String, Result<String, String>)>,
+    parse: fn(&str) -> Result<T, String>,
+    default: fn() -> T,
+) -> T {
+    match *file {
+        Some((path, ref text)) => text.clone().and_then(|text| parse(&text)).unwrap_or_else(|e| {
+            eprintln!("Error: could not load keymap {}: {}. Using the default.", path, e);
+            default()
+        }),
+        None => default(),
+    }
+}
+
+/// Return the disassemblies of the words that differ between the ROMs `old` and `new`, one line
+/// per word: `~` marks a changed instruction, and `+` and `-` one inserted into or removed from the
+/// end of `new`.
+fn diff_roms(old: &[u8], new: &[u8]) -> String {
+    let mut diff = String::new();
+    for (address, old, new) in chip_8::diff_roms(old, new) {
+        let line = match (old, new) {
+            (Some(old), Some(new)) => format!(
+                "~ 0x{:03X}: {:04X}  {:<16} -> {:04X}  {}",
+                address,
+                old,
+                chip_8::disassemble(old),
+                new,
+                chip_8::disassemble(new)
+            ),
+            (None, Some(new)) => {
+                format!("+ 0x{:03X}: {:04X}  {}", address, new, chip_8::disassemble(new))
+            }
+            (Some(old), _) => {
+                format!("- 0x{:03X}: {:04X}  {}", address, old, chip_8::disassemble(old))
+            }
+            (None, None) => continue,
+        };
+        diff.push_str(&line);
+        diff.push('\n');
+    }
+    diff
+}
+
+/// Print an error and the usage, then exit.
+fn usage_error(error: &str) -> ! {
+    eprintln!("Error: {}", error);
+    println!(
+        "Usage: chip-8 [--speed <n>] [--fps <n>] [--no-vsync] [--scale <n>] [--fg <RRGGBB>] \
+         [--bg <RRGGBB>] [--tui] [--headless <frames>] [--permissive] [--auto] \
+         [--keymap <path>] [--debug] [--step-repeat <n>] [--crash-dump <path>] \
+         [--builtin <name>] <file>"
+    );
+    println!("       chip-8 --diff-roms <old file> <new file>");
+    println!("Built-in ROMs: {}", chip_8::builtin_roms().join(", "));
+    std::process::exit(1);
+}
 
 fn main() -> std::io::Result<()> {
-    let mut processor = if let Some(filename) = std::env::args().skip(1).next() {
+    let options = Options::parse(std::env::args().skip(1)).unwrap_or_else(|e| usage_error(&e));
+
+    if let Some((old, new)) = options.diff_roms {
+        print!("{}", diff_roms(&std::fs::read(old)?, &std::fs::read(new)?));
+        return Ok(());
+    }
+
+    let mut processor = if let Some(name) = options.builtin {
+        let mut processor = Processor::new();
+        if let Err(e) = processor.load_builtin(&name) {
+            usage_error(&e.to_string());
+        }
+        processor
+    } else if let Some(filename) = options.file {
         let mut file = File::open(filename)?;
         let mut contents: Vec<u8> = Vec::new();
         file.read_to_end(&mut contents)?;
-        Processor::with_file(&contents)
+        match Processor::try_from(&contents[..]) {
+            Ok(processor) => processor,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
     } else {
-        eprintln!("Error: no file found.");
-        println!("Usage: chip-8 <file>");
-        std::process::exit(1);
+        usage_error("no file found.");
     };
 
+    if options.auto {
+        match processor.auto_configure() {
+            Some(config) if config.xochip => println!("Detected platform: XO-CHIP."),
+            Some(_) => println!("Detected platform: SUPER-CHIP."),
+            None => println!("Detected platform: CHIP-8. Keeping the default quirks."),
+        }
+    }
+    processor.quirks.skip_unknown_opcodes = options.permissive;
+    let trace = record_trace(&mut processor);
+
+    // The keymap file maps both the keyboard keys and the gamepad buttons.
+    let keymap_file = options
+        .keymap
+        .as_ref()
+        .map(|path| (path, std::fs::read_to_string(path).map_err(|e| e.to_string())));
+    let keymap = load_mapping(&keymap_file, keymap::parse_keymap, keymap::default_keymap);
+    #[cfg(feature = "gamepad")]
+    let button_map = load_mapping(
+        &keymap_file,
+        gamepad::parse_button_map,
+        gamepad::default_button_map,
+    );
+
+    let mut colours = PLANE_COLOURS;
+    colours[0] = options.background;
+    colours[1] = options.foreground;
+
+    if let Some(frames) = options.headless {
+        run_headless(processor, frames, options.speed, &options.crash_dump, &trace);
+        return Ok(());
+    }
+
+    if options.tui {
+        #[cfg(unix)]
+        return run_tui(
+            processor,
+            options.speed,
+            &options.crash_dump,
+            &trace,
+            &keymap,
+            &colours,
+        );
+        #[cfg(not(unix))]
+        usage_error("--tui is only supported on Unix.");
+    }
+
     let mut events_loop = glutin::EventsLoop::new();
     let window = glutin::WindowBuilder::new()
         .with_title("CHIP-8")
-        .with_dimensions(glutin::dpi::LogicalSize::new(640.0, 340.0))
+        .with_dimensions(glutin::dpi::LogicalSize::new(
+            f64::from(options.scale) * WIDTH as f64,
+            f64::from(options.scale) * HEIGHT as f64,
+        ))
         .with_resizable(false);
 
-    let context = glutin::ContextBuilder::new().with_vsync(true);
+    let context = glutin::ContextBuilder::new().with_vsync(options.vsync);
     let gl_window = glutin::GlWindow::new(window, context, &events_loop).unwrap();
 
     unsafe {
         gl_window.make_current().unwrap();
     }
 
-    let mut graphics = Graphics::new();
-    graphics.init(&gl_window).unwrap();
+    let mut graphics = Graphics::new(gl_window).unwrap();
+
+    #[cfg(feature = "gamepad")]
+    let mut gilrs = gamepad::Gilrs::new()
+        .map_err(|e| eprintln!("Error: could not open gamepads: {}", e))
+        .ok();
+
+    // There is no audio output yet, so report the buzzer on the terminal, unless it is muted.
+    let muted = Rc::new(Cell::new(false));
+    let beep_muted = Rc::clone(&muted);
+    processor.set_beep_callback(move |beeping| {
+        if beep_muted.get() {
+            return;
+        }
+        if beeping {
+            println!("Beep started.");
+        } else {
+            println!("Beep stopped.");
+        }
+    });
+
+    let mut frame_limiter = options.fps.map(FrameLimiter::new);
+
+    // Instructions are executed at the clock speed and the timers tick at 60 Hz, each on their own
+    // schedule, independently of how often the display is presented.
+    let cycle_period = Duration::from_secs(1) / options.speed;
+    let mut next_cycle = Instant::now();
+    let timer_period = Duration::from_secs(1) / 60;
+    let mut next_timer_tick = Instant::now() + timer_period;
+
+    // The text typed into the prompt, if it is open. While the prompt is open, the processor is
+    // paused and the keypad does not receive input.
+    let mut prompt: Option<String> = None;
+    // The address and the unknown opcode that stopped the processor, while asking whether to skip
+    // it or abort. Like the prompt, this pauses the processor.
+    let mut unknown_opcode: Option<(usize, u16)> = None;
+    // Whether the processor is paused and the buzzer muted, and the number of instructions
+    // requested while paused.
+    let mut playback = Playback::default();
+    let mut steps_requested = 0;
+    let mut step_repeat = KeyRepeat::new(options.step_repeat);
+    let debug = options.debug;
+    let crash_dump = &options.crash_dump;
+    // Screenshots are drawn at the same scale as the window.
+    #[cfg(feature = "screenshot")]
+    let screenshot_block = options.scale as usize;
+    let mut title = String::from("CHIP-8");
 
     let mut closed = false;
     while !closed {
@@ -44,32 +720,94 @@ fn main() -> std::io::Result<()> {
             if let Event::WindowEvent { event, .. } = e {
                 match event {
                     WindowEvent::CloseRequested => closed = true,
+                    WindowEvent::ReceivedCharacter(c) => {
+                        if let Some(ref mut text) = prompt {
+                            if c.is_ascii_alphanumeric() || c == ' ' {
+                                text.push(c);
+                            }
+                        }
+                    }
                     WindowEvent::KeyboardInput { input, .. } => {
                         if let Some(keycode) = input.virtual_keycode {
                             let pressed = input.state == ElementState::Pressed;
+                            if let Some((pc, opcode)) = unknown_opcode {
+                                match keycode {
+                                    S | Return if pressed => {
+                                        skip_unknown_opcode(&mut processor, pc);
+                                        unknown_opcode = None;
+                                    }
+                                    Escape if pressed => {
+                                        let e = chip_8::Error::UnknownOpcode { pc, opcode };
+                                        crash(crash_dump, &processor, &trace, &e);
+                                    }
+                                    _ => (),
+                                }
+                                return;
+                            }
+                            if let Some(mut text) = prompt.take() {
+                                match keycode {
+                                    Return if pressed => match parse_command(&text) {
+                                        Ok(Command::Poke(address, value)) => {
+                                            if let Err(e) = processor.poke(address, value) {
+                                                eprintln!("Error: {}", e);
+                                            }
+                                        }
+                                        // Stay paused afterwards, to inspect the result.
+                                        Ok(Command::Step(steps)) => {
+                                            if !playback.paused {
+                                                playback.toggle_pause();
+                                            }
+                                            steps_requested += steps;
+                                        }
+                                        Err(e) => eprintln!("Error: {}.", e),
+                                    },
+                                    Escape if pressed => (),
+                                    Back if pressed => {
+                                        text.pop();
+                                        prompt = Some(text);
+                                    }
+                                    _ => prompt = Some(text),
+                                }
+                                return;
+                            }
                             match keycode {
-                                Key1 => processor.set_key(0x1, pressed),
-                                Key2 => processor.set_key(0x2, pressed),
-                                Key3 => processor.set_key(0x3, pressed),
-                                Key4 => processor.set_key(0xC, pressed),
-                                Q => processor.set_key(0x4, pressed),
-                                W => processor.set_key(0x5, pressed),
-                                E => processor.set_key(0x6, pressed),
-                                R => processor.set_key(0xD, pressed),
-                                A => processor.set_key(0x7, pressed),
-                                S => processor.set_key(0x8, pressed),
-                                D => processor.set_key(0x9, pressed),
-                                F => processor.set_key(0xE, pressed),
-                                Z => processor.set_key(0xA, pressed),
-                                X => processor.set_key(0x0, pressed),
-                                C => processor.set_key(0xB, pressed),
-                                V => processor.set_key(0xF, pressed),
+                                _ if keymap.contains_key(&keycode) => {
+                                    processor.set_key(keymap[&keycode], pressed)
+                                }
                                 Escape => closed = true,
+                                F5 if pressed => processor.reset(),
+                                #[cfg(feature = "screenshot")]
+                                F2 if pressed => {
+                                    let image = screenshot::display_to_image(
+                                        &processor,
+                                        screenshot_block,
+                                        colours[1],
+                                        colours[0],
+                                    );
+                                    match screenshot::save(&image) {
+                                        Ok(path) => println!("Saved a screenshot to {}.", path),
+                                        Err(e) => eprintln!("Error: could not save screenshot: {}", e),
+                                    }
+                                }
+                                P if pressed => playback.toggle_pause(),
+                                M if pressed => playback.toggle_mute(),
+                                Space if pressed => playback.toggle_pause_and_mute(),
+                                // Holding N keeps stepping, at the rate of `step_repeat`.
+                                N if step_repeat.key(pressed, Instant::now())
+                                    && playback.paused =>
+                                {
+                                    steps_requested += 1
+                                }
+                                Tab if debug && pressed => {
+                                    processor.set_keys(0);
+                                    prompt = Some(String::new());
+                                }
                                 // Question mark.
                                 Slash if input.modifiers.shift => println!(
-                                    "index = 0x{:X}, opcode = 0x{:04X}",
+                                    "index = 0x{:X}, opcode = 0x{:04X} ({})",
                                     processor.program_counter,
-                                    processor.opcode()
+                                    processor.opcode(),
+                                    chip_8::disassemble(processor.opcode())
                                 ),
                                 _ => (),
                             }
@@ -80,21 +818,205 @@ fn main() -> std::io::Result<()> {
             }
         });
 
-        processor.run_cycle().unwrap();
+        #[cfg(feature = "gamepad")]
+        {
+            // Like the keyboard, the gamepad does not press keys while the prompt is open.
+            if let (Some(gilrs), None) = (gilrs.as_mut(), &prompt) {
+                gamepad::poll(gilrs, &button_map, &mut processor);
+            }
+        }
+
+        muted.set(playback.muted);
 
-        if processor.draw {
-            graphics.clear_colour(0.0, 0.0, 0.0, 1.0);
-            for y in 0..HEIGHT {
-                for x in 0..WIDTH {
-                    if processor.display[x + y * WIDTH] {
-                        graphics.draw_square_at(x, y);
+        let new_title = match (unknown_opcode, &prompt) {
+            (Some((pc, opcode)), _) => format!(
+                "CHIP-8 - unknown opcode {:04X} at {:03X}: S to skip, Escape to abort",
+                opcode, pc
+            ),
+            (None, Some(text)) => format!("CHIP-8 - <address> <value> or step <n>: {}", text),
+            (None, None) => match (playback.paused, playback.muted) {
+                (true, true) => String::from("CHIP-8 - paused, muted"),
+                (true, false) => String::from("CHIP-8 - paused"),
+                (false, true) => String::from("CHIP-8 - muted"),
+                (false, false) => String::from("CHIP-8"),
+            },
+        };
+        if new_title != title {
+            graphics.window().set_title(&new_title);
+            title = new_title;
+        }
+
+        if prompt.is_some() || unknown_opcode.is_some() {
+            next_cycle = Instant::now();
+            next_timer_tick = Instant::now() + timer_period;
+            // Only typing happens while the prompt is open or an unknown opcode is pending, so poll
+            // for events once per timer period instead of spinning.
+            std::thread::sleep(timer_period);
+            continue;
+        }
+
+        if playback.paused {
+            // While paused, N executes a single instruction and ticks the timers once, repeating
+            // while it is held.
+            if step_repeat.repeat_due(Instant::now()) {
+                steps_requested += 1;
+            }
+            if steps_requested > 0 {
+                match step_cycles(&mut processor, steps_requested) {
+                    Err(chip_8::Error::UnknownOpcode { pc, opcode }) => {
+                        unknown_opcode = Some((pc, opcode))
+                    }
+                    Err(e) => crash(crash_dump, &processor, &trace, &e),
+                    Ok(()) => (),
+                }
+                steps_requested = 0;
+            }
+            next_cycle = Instant::now() + cycle_period;
+            next_timer_tick = Instant::now() + timer_period;
+        } else {
+            while Instant::now() >= next_cycle
+                && !processor.is_halted()
+                && unknown_opcode.is_none()
+            {
+                match processor.step() {
+                    Err(chip_8::Error::UnknownOpcode { pc, opcode }) => {
+                        unknown_opcode = Some((pc, opcode))
                     }
+                    Err(e) => crash(crash_dump, &processor, &trace, &e),
+                    Ok(_) => (),
                 }
+                next_cycle += cycle_period;
+            }
+            // Don't try to catch up with more than a frame of instructions after falling behind,
+            // e.g. while the window was being moved.
+            if Instant::now() > next_cycle + timer_period {
+                next_cycle = Instant::now();
+            }
+
+            while Instant::now() >= next_timer_tick {
+                processor.tick_timers();
+                next_timer_tick += timer_period;
             }
-            gl_window.swap_buffers().unwrap();
-            processor.draw = false;
+        }
+
+        if processor.is_halted() {
+            closed = true;
+        }
+
+        // Tint the background of the region changed since the last frame when debugging.
+        let dirty_region = if options.debug { processor.dirty_region() } else { None };
+        // Check for a change first, so that a frame slot is only used up when a frame is presented.
+        if processor.needs_present()
+            && frame_limiter
+                .as_mut()
+                .map_or(true, |limiter| limiter.frame_due())
+        {
+            processor.mark_presented();
+            renderer::draw(&mut graphics, &processor, &colours, dirty_region);
+        }
+
+        // Sleep until the next instruction or timer tick is due, instead of spinning.
+        if let Some(wait) = next_cycle.min(next_timer_tick).checked_duration_since(Instant::now()) {
+            std::thread::sleep(wait);
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_roms_prints_changed_address() {
+        let diff = diff_roms(&[0x60, 0x05, 0x70, 0x01], &[0x60, 0x05, 0x70, 0x02]);
+        assert_eq!(diff, "~ 0x202: 7001  ADD V0, 0x01     -> 7002  ADD V0, 0x02\n");
+    }
+
+    #[test]
+    fn pause_and_mute_toggle_together() {
+        let mut playback = Playback::default();
+        playback.toggle_pause_and_mute();
+        assert!(playback.paused && playback.muted);
+        playback.toggle_pause_and_mute();
+        assert_eq!(playback, Playback::default());
+
+        // Resuming with P ends the combined pause too.
+        playback.toggle_pause_and_mute();
+        playback.toggle_pause();
+        assert_eq!(playback, Playback::default());
+
+        // A buzzer muted before pausing stays muted after resuming.
+        playback.toggle_mute();
+        playback.toggle_pause_and_mute();
+        assert!(playback.paused && playback.muted);
+        playback.toggle_pause_and_mute();
+        assert!(!playback.paused && playback.muted);
+
+        // Muting the buzzer by hand during the combined pause keeps it muted after resuming.
+        playback.toggle_mute();
+        playback.toggle_pause_and_mute();
+        playback.toggle_mute();
+        playback.toggle_mute();
+        playback.toggle_pause();
+        assert!(!playback.paused && playback.muted);
+    }
+
+    #[test]
+    fn step_command_executes_instructions() {
+        assert_eq!(parse_command("step 50"), Ok(Command::Step(50)));
+        assert_eq!(parse_command(" step "), Ok(Command::Step(1)));
+        assert_eq!(parse_command("2A0 FF"), Ok(Command::Poke(0x2A0, 0xFF)));
+        assert_eq!(parse_command("step x"), Err(String::from("invalid number of steps: x")));
+        assert_eq!(parse_command("step 1 2"), Err(String::from("expected a number of steps")));
+
+        // 60 times ADD V0, 0x01
+        let rom: Vec<u8> = [0x70, 0x01].iter().cycle().take(120).cloned().collect();
+        let mut processor = Processor::try_from(&rom[..]).unwrap();
+        let steps = match parse_command("step 50") {
+            Ok(Command::Step(steps)) => steps,
+            command => panic!("{:?}", command),
+        };
+        step_cycles(&mut processor, steps).unwrap();
+        assert_eq!(processor.program_counter, 0x200 + 2 * 50);
+        assert_eq!(processor.registers[0], 50);
+    }
+
+    #[test]
+    fn skipping_unknown_opcode_continues_execution() {
+        // LD V0, 0x07; 8xyF (unknown); LD V1, 0x03
+        let rom = [0x60, 0x07, 0x80, 0x0F, 0x61, 0x03];
+        let mut processor = Processor::try_from(&rom[..]).unwrap();
+        let pc = match step_cycles(&mut processor, 2) {
+            Err(chip_8::Error::UnknownOpcode { pc, opcode: 0x800F }) => pc,
+            result => panic!("{:?}", result),
+        };
+        assert_eq!(pc, 0x202);
+
+        skip_unknown_opcode(&mut processor, pc);
+        assert_eq!(processor.program_counter, 0x204);
+        step_cycles(&mut processor, 1).unwrap();
+        assert_eq!(processor.program_counter, 0x206);
+        assert_eq!(processor.registers[..2], [0x07, 0x03]);
+    }
+
+    #[test]
+    fn parse_poke_reads_hex_address_and_value() {
+        assert_eq!(parse_poke("2A0 FF"), Ok((0x2A0, 0xFF)));
+        assert_eq!(parse_poke("  2a0\t0f "), Ok((0x2A0, 0x0F)));
+        assert_eq!(parse_poke("2A0"), Err(String::from("expected an address and a value")));
+        assert_eq!(parse_poke("2A0 FF 00"), Err(String::from("expected an address and a value")));
+        assert_eq!(parse_poke("2G0 FF"), Err(String::from("invalid address: 2G0")));
+        assert_eq!(parse_poke("2A0 100"), Err(String::from("invalid value: 100")));
+    }
+
+    #[test]
+    fn parse_colour_rejects_malformed_hex() {
+        assert_eq!(parse_colour("#FF8000"), Ok((1.0, 128.0 / 255.0, 0.0)));
+        assert_eq!(parse_colour("00ff00"), Ok((0.0, 1.0, 0.0)));
+        for &text in &["", "#", "FFF", "FF80001", "GG0000", "#FF 000", "+FFFFF"] {
+            assert_eq!(parse_colour(text), Err(format!("Invalid colour: {}.", text)));
+        }
+    }
+}
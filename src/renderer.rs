@@ -0,0 +1,134 @@
+//! Drawing the display of a `Processor`, independently of the backend.
+
+use chip_8::{Processor, Region};
+
+/// A colour as its red, green and blue components, from 0 to 1.
+pub type Colour = (f32, f32, f32);
+
+/// The default colours of the pixels by the combination of XO-CHIP bit-planes they are set in:
+/// none, the first, the second and both. Outside XO-CHIP mode, only the first plane is used.
+pub const PLANE_COLOURS: [Colour; 4] = [
+    (0.0, 0.0, 0.0),
+    (1.0, 1.0, 1.0),
+    (1.0, 0.67, 0.0),
+    (0.6, 0.6, 0.6),
+];
+
+/// The colour of the background of the region changed since the last frame, when debugging.
+const DIRTY_REGION_COLOUR: Colour = (0.4, 0.0, 0.0);
+
+/// A backend that draws the display of a `Processor`.
+pub trait Renderer {
+    /// Set the size of the display in pixels, for the frames drawn after this call.
+    fn set_resolution(&mut self, width: usize, height: usize);
+
+    /// Start a new frame, filled with `colour`.
+    fn clear(&mut self, colour: Colour);
+
+    /// Draw the pixel at (`x`, `y`) in `colour`.
+    fn draw_pixel(&mut self, x: usize, y: usize, colour: Colour);
+
+    /// Show the frame drawn since the last `clear`.
+    fn present(&mut self);
+}
+
+/// Draw a frame of the display of `processor` with `renderer`, using `colours` like
+/// `PLANE_COLOURS`.
+///
+/// If `dirty_region` is given, its background is tinted to show what changed since the last
+/// frame.
+pub fn draw<R: Renderer>(
+    renderer: &mut R,
+    processor: &Processor,
+    colours: &[Colour; 4],
+    dirty_region: Option<Region>,
+) {
+    let (width, height) = (processor.width(), processor.height());
+    renderer.set_resolution(width, height);
+    renderer.clear(colours[0]);
+    if let Some(region) = dirty_region {
+        for y in region.y..region.y + region.height {
+            for x in region.x..region.x + region.width {
+                renderer.draw_pixel(x, y, DIRTY_REGION_COLOUR);
+            }
+        }
+    }
+    for y in 0..height {
+        for x in 0..width {
+            let colour = processor.pixel_colour(x, y);
+            if colour != 0 {
+                renderer.draw_pixel(x, y, colours[colour as usize]);
+            }
+        }
+    }
+    renderer.present();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `Renderer` that records the calls made to it.
+    #[derive(Default)]
+    struct Recorder {
+        calls: Vec<String>,
+    }
+
+    impl Renderer for Recorder {
+        fn set_resolution(&mut self, width: usize, height: usize) {
+            self.calls.push(format!("set_resolution {} {}", width, height));
+        }
+
+        fn clear(&mut self, colour: Colour) {
+            self.calls.push(format!("clear {:?}", colour));
+        }
+
+        fn draw_pixel(&mut self, x: usize, y: usize, colour: Colour) {
+            self.calls.push(format!("draw_pixel {} {} {:?}", x, y, colour));
+        }
+
+        fn present(&mut self) {
+            self.calls.push(String::from("present"));
+        }
+    }
+
+    #[test]
+    fn draw_clears_then_draws_lit_pixels() {
+        let mut processor = Processor::with_seed(0);
+        processor.set_pixel(2, 1, true);
+        processor.set_pixel(63, 31, true);
+        let mut recorder = Recorder::default();
+        draw(&mut recorder, &processor, &PLANE_COLOURS, None);
+        assert_eq!(
+            recorder.calls,
+            [
+                "set_resolution 64 32",
+                "clear (0.0, 0.0, 0.0)",
+                "draw_pixel 2 1 (1.0, 1.0, 1.0)",
+                "draw_pixel 63 31 (1.0, 1.0, 1.0)",
+                "present",
+            ]
+        );
+    }
+
+    #[test]
+    fn draw_tints_dirty_region() {
+        let processor = Processor::with_seed(0);
+        let mut recorder = Recorder::default();
+        let region = Region {
+            x: 4,
+            y: 5,
+            width: 2,
+            height: 1,
+        };
+        draw(&mut recorder, &processor, &PLANE_COLOURS, Some(region));
+        assert_eq!(
+            recorder.calls[2..],
+            [
+                "draw_pixel 4 5 (0.4, 0.0, 0.0)",
+                "draw_pixel 5 5 (0.4, 0.0, 0.0)",
+                "present",
+            ]
+        );
+    }
+}
@@ -0,0 +1,97 @@
+//! Saving screenshots of the display as PNG files.
+
+extern crate png;
+
+use chip_8::Processor;
+use renderer::Colour;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// An RGB image, 8 bits per channel, row by row.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+/// Convert the display of `processor` to an image, drawing each pixel as a square of `block` by
+/// `block` pixels in `foreground` if it is set and `background` otherwise.
+pub fn display_to_image(
+    processor: &Processor,
+    block: usize,
+    foreground: Colour,
+    background: Colour,
+) -> Image {
+    let to_rgb = |(red, green, blue): Colour| {
+        [
+            (red * 255.0).round() as u8,
+            (green * 255.0).round() as u8,
+            (blue * 255.0).round() as u8,
+        ]
+    };
+    let (foreground, background) = (to_rgb(foreground), to_rgb(background));
+    let (width, height) = (processor.width(), processor.height());
+    let mut pixels = Vec::with_capacity(width * height * block * block * 3);
+    for y in 0..height * block {
+        for x in 0..width * block {
+            let (x, y) = (x / block, y / block);
+            let set = processor.get_pixel(x, y);
+            pixels.extend_from_slice(if set { &foreground } else { &background });
+        }
+    }
+    Image {
+        width: width * block,
+        height: height * block,
+        pixels,
+    }
+}
+
+/// Save `image` to a PNG file named after the current time in the working directory, and return
+/// the name of the file.
+pub fn save(image: &Image) -> io::Result<String> {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let path = format!("chip-8-{}{:03}.png", time.as_secs(), time.subsec_millis());
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(&path)?),
+        image.width as u32,
+        image.height as u32,
+    );
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder
+        .write_header()
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    writer
+        .write_image_data(&image.pixels)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_to_image_scales_pixels() {
+        let mut processor = Processor::with_seed(0);
+        processor.set_pixel(0, 0, true);
+        processor.set_pixel(63, 31, true);
+        let image = display_to_image(&processor, 2, (1.0, 0.5, 0.0), (0.0, 0.0, 1.0));
+        assert_eq!((image.width, image.height), (128, 64));
+        assert_eq!(image.pixels.len(), 128 * 64 * 3);
+
+        let pixel = |x: usize, y: usize| &image.pixels[(x + y * image.width) * 3..][..3];
+        let (foreground, background) = ([255, 128, 0], [0, 0, 255]);
+        assert_eq!(pixel(0, 0), foreground);
+        assert_eq!(pixel(1, 1), foreground);
+        assert_eq!(pixel(2, 0), background);
+        assert_eq!(pixel(127, 0), background);
+        assert_eq!(pixel(0, 63), background);
+        assert_eq!(pixel(126, 62), foreground);
+        assert_eq!(pixel(127, 63), foreground);
+    }
+}
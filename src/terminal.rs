@@ -0,0 +1,233 @@
+//! A `Renderer` and keyboard input for running in a terminal.
+//!
+//! Two rows of pixels are drawn per line of text with the upper half block character (`▀`),
+//! whose foreground colour is the upper pixel and whose background colour is the lower pixel.
+
+extern crate libc;
+
+use glutin::VirtualKeyCode;
+use keymap::{self, Keymap};
+use renderer::{Colour, Renderer};
+use std::io;
+use std::io::prelude::*;
+use std::mem;
+use std::time::{Duration, Instant};
+
+/// How long a key stays pressed after its character is read. Terminals only report key presses,
+/// and repeat them while a key is held down, so a key is released once it stops repeating.
+const KEY_HOLD: Duration = Duration::from_millis(150);
+
+/// Return the text drawing `pixels`, a frame of `width` by `height` pixels in which each pixel is
+/// a colour, row by row.
+///
+/// Each line starts by moving the cursor to its start, so the frame replaces the previous one.
+pub fn frame_to_string(width: usize, height: usize, pixels: &[Colour]) -> String {
+    let to_rgb = |(red, green, blue): Colour| {
+        (
+            (red * 255.0).round() as u8,
+            (green * 255.0).round() as u8,
+            (blue * 255.0).round() as u8,
+        )
+    };
+    let mut text = String::new();
+    for row in 0..(height + 1) / 2 {
+        text.push_str(&format!("\x1b[{};1H", row + 1));
+        for x in 0..width {
+            let (top, bottom) = (2 * row * width + x, (2 * row + 1) * width + x);
+            let (r, g, b) = to_rgb(pixels[top]);
+            text.push_str(&format!("\x1b[38;2;{};{};{}m", r, g, b));
+            if let Some(&bottom) = pixels.get(bottom) {
+                let (r, g, b) = to_rgb(bottom);
+                text.push_str(&format!("\x1b[48;2;{};{};{}m", r, g, b));
+            }
+            text.push('▀');
+        }
+        text.push_str("\x1b[0m");
+    }
+    text
+}
+
+/// Return the characters typed in `bytes`, read from the terminal at once, or `None` if Escape or
+/// Ctrl-C was typed, to quit.
+///
+/// Keys such as the arrow keys send escape sequences starting with an Escape byte, so Escape is
+/// only taken to be typed when it is the last byte read. Control sequences (`ESC [`, parameters,
+/// and a final byte) and other escaped characters (`ESC` and one byte) are skipped.
+fn parse_input(bytes: &[u8]) -> Option<Vec<char>> {
+    let mut chars = Vec::new();
+    let mut bytes = bytes.iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            0x03 => return None,
+            0x1B => match bytes.next() {
+                None => return None,
+                Some(b'[') => {
+                    // Skip the parameter and intermediate bytes, up to and including the final
+                    // byte.
+                    for &byte in &mut bytes {
+                        if (0x40..=0x7E).contains(&byte) {
+                            break;
+                        }
+                    }
+                }
+                Some(_) => {}
+            },
+            _ => chars.push(byte as char),
+        }
+    }
+    Some(chars)
+}
+
+/// Return the key on a keyboard that types `c`, if any.
+fn key_for_char(c: char) -> Option<VirtualKeyCode> {
+    let name = match c {
+        '0'..='9' => format!("Key{}", c),
+        'a'..='z' | 'A'..='Z' => c.to_ascii_uppercase().to_string(),
+        ' ' => String::from("Space"),
+        _ => c.to_string(),
+    };
+    keymap::key_from_name(&name)
+}
+
+/// The terminal `Renderer`, which also reads the keyboard.
+///
+/// The terminal is switched to raw mode while this exists.
+pub struct Terminal {
+    /// The terminal settings to restore when dropped.
+    original_settings: libc::termios,
+    width: usize,
+    height: usize,
+    /// The frame drawn since the last clear.
+    pixels: Vec<Colour>,
+    /// When each key on the keypad was last typed.
+    pressed_at: [Option<Instant>; 16],
+}
+
+impl Terminal {
+    /// Switch the terminal to raw mode and clear it.
+    pub fn new() -> io::Result<Terminal> {
+        let original_settings = unsafe {
+            let mut settings: libc::termios = mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut settings) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let original_settings = settings;
+            libc::cfmakeraw(&mut settings);
+            // Make reads return immediately, even if nothing was typed.
+            settings.c_cc[libc::VMIN] = 0;
+            settings.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &settings) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            original_settings
+        };
+        // Clear the screen and hide the cursor.
+        print!("\x1b[2J\x1b[?25l");
+        io::stdout().flush()?;
+        Ok(Terminal {
+            original_settings,
+            width: 0,
+            height: 0,
+            pixels: Vec::new(),
+            pressed_at: [None; 16],
+        })
+    }
+
+    /// Read the characters typed since the last call, and update the keypad of `processor`
+    /// according to `keymap`.
+    ///
+    /// Return `false` if Escape or Ctrl-C was typed, to quit.
+    pub fn read_keys(&mut self, keymap: &Keymap, processor: &mut ::chip_8::Processor) -> bool {
+        let mut buffer = [0; 32];
+        let read = io::stdin().read(&mut buffer).unwrap_or(0);
+        let now = Instant::now();
+        let chars = match parse_input(&buffer[..read]) {
+            Some(chars) => chars,
+            None => return false,
+        };
+        for c in chars {
+            if let Some(&key) = key_for_char(c).and_then(|key| keymap.get(&key)) {
+                self.pressed_at[key] = Some(now);
+            }
+        }
+        for (key, pressed_at) in self.pressed_at.iter_mut().enumerate() {
+            if pressed_at.map_or(false, |time| now - time > KEY_HOLD) {
+                *pressed_at = None;
+            }
+            processor.set_key(key, pressed_at.is_some());
+        }
+        true
+    }
+}
+
+impl Renderer for Terminal {
+    fn set_resolution(&mut self, width: usize, height: usize) {
+        if (width, height) != (self.width, self.height) {
+            self.width = width;
+            self.height = height;
+            print!("\x1b[2J");
+        }
+    }
+
+    fn clear(&mut self, colour: Colour) {
+        self.pixels.clear();
+        self.pixels.resize(self.width * self.height, colour);
+    }
+
+    fn draw_pixel(&mut self, x: usize, y: usize, colour: Colour) {
+        self.pixels[x + y * self.width] = colour;
+    }
+
+    fn present(&mut self) {
+        let frame = frame_to_string(self.width, self.height, &self.pixels);
+        let mut stdout = io::stdout();
+        // There is nowhere to report a failure to draw to the terminal.
+        let _ = stdout.write_all(frame.as_bytes());
+        let _ = stdout.flush();
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        // Show the cursor again below the display, and restore the terminal settings.
+        print!("\x1b[0m\x1b[?25h\x1b[{};1H\r\n", (self.height + 1) / 2 + 1);
+        let _ = io::stdout().flush();
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original_settings);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_quits_only_as_last_byte() {
+        assert_eq!(parse_input(b"\x1b"), None);
+        assert_eq!(parse_input(b"a\x1b"), None);
+        assert_eq!(parse_input(b"a\x03b"), None);
+        assert_eq!(parse_input(b"w"), Some(vec!['w']));
+    }
+
+    #[test]
+    fn escape_sequences_are_skipped() {
+        // The up and right arrow keys, and a sequence with parameters.
+        assert_eq!(parse_input(b"\x1b[Aa\x1b[C"), Some(vec!['a']));
+        assert_eq!(parse_input(b"\x1b[1;5Dq"), Some(vec!['q']));
+        // Alt-x.
+        assert_eq!(parse_input(b"\x1bxs"), Some(vec!['s']));
+    }
+
+    #[test]
+    fn frame_to_string_draws_two_rows_per_line() {
+        let (off, on) = ((0.0, 0.0, 0.0), (1.0, 1.0, 1.0));
+        let text = frame_to_string(2, 3, &[on, off, off, on, on, off]);
+        assert_eq!(
+            text,
+            "\x1b[1;1H\x1b[38;2;255;255;255m\x1b[48;2;0;0;0m▀\
+             \x1b[38;2;0;0;0m\x1b[48;2;255;255;255m▀\x1b[0m\
+             \x1b[2;1H\x1b[38;2;255;255;255m▀\x1b[38;2;0;0;0m▀\x1b[0m"
+        );
+    }
+}
@@ -0,0 +1,36 @@
+//! Tests of the crash dumps the interpreter writes when a ROM fails.
+
+#![cfg(feature = "std")]
+
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn unknown_opcode_writes_crash_dump() {
+    let directory = std::env::temp_dir();
+    let rom = directory.join(format!("chip-8-unknown-opcode-{}.ch8", std::process::id()));
+    let dump = directory.join(format!("chip-8-crash-{}.txt", std::process::id()));
+    // LD V0, 0x07; 800F
+    fs::write(&rom, [0x60, 0x07, 0x80, 0x0F]).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_chip-8"))
+        .args(["--headless", "1", "--crash-dump"])
+        .arg(&dump)
+        .arg(&rom)
+        .output()
+        .unwrap();
+    let contents = fs::read_to_string(&dump);
+    fs::remove_file(&rom).unwrap();
+    let _ = fs::remove_file(&dump);
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("A crash dump was written to"), "{}", stderr);
+    let contents = contents.unwrap();
+    assert!(contents.starts_with("CHIP-8 crash dump\nError: Unknown opcode at 0x202: 0x800F.\n"));
+    assert!(contents.contains("\n[state]\n"));
+    assert!(contents.contains("\n[trace]\n0x200: 6007  LD V0, 0x07\n0x202: 800F  DW 0x800F\n"));
+    assert!(contents.contains(&format!("\n[display]\n{}\n", ".".repeat(64))));
+    #[cfg(feature = "serde")]
+    assert!(contents.contains("\n[state]\n{\"memory\":["));
+}